@@ -1,99 +1,99 @@
+use std::ops::Range;
+use ropey::Rope;
 use vek::*;
 use super::Line;
 
-#[derive(Clone)]
+// Text is stored without the implicit trailing newline that terminates the last line, so
+// `Content::len` is the number of characters in the rope.
+#[derive(Clone, PartialEq)]
 pub struct Content {
-    lines: Vec<Vec<char>>,
+    rope: Rope,
 }
 
 impl Content {
     pub fn len(&self) -> usize {
-        self
-            .lines()
-            .map(|line| line.len())
-            .sum::<usize>().saturating_sub(1)
+        self.rope.len_chars()
     }
 
     pub fn lines(&self) -> impl ExactSizeIterator<Item=Line> {
-        self.lines
-            .iter()
-            .map(|line| Line::from(line.as_slice()))
+        (0..self.rope.len_lines())
+            .map(move |line| self.line_unchecked(line))
     }
 
     pub fn line(&self, line: usize) -> Option<Line> {
-        self
-            .lines()
-            .skip(line)
-            .next()
+        if line < self.rope.len_lines() {
+            Some(self.line_unchecked(line))
+        } else {
+            None
+        }
+    }
+
+    fn line_unchecked(&self, line: usize) -> Line<'_> {
+        let start = self.rope.line_to_char(line);
+        let end = if line + 1 < self.rope.len_lines() {
+            self.rope.line_to_char(line + 1) - 1 // Exclude the newline
+        } else {
+            self.rope.len_chars()
+        };
+        Line::from(self.rope.slice(start..end))
     }
 
     pub fn chars(&self) -> impl Iterator<Item=char> + '_ {
-        self.lines
-            .iter()
-            .map(|line| line
-                .iter()
-                .copied()
-                .chain(Some('\n')))
-            .flatten()
+        self.rope
+            .chars()
+            .chain(Some('\n'))
     }
 
-    pub fn char_at(&self, pos: usize) -> Option<char> {
-        let (rank, line) = self.pos_to_rank_line(pos).into_tuple();
-        self.line(line)?.chars().nth(rank)
-    }
-
-    pub fn pos_to_rank_line(&self, mut pos: usize) -> Vec2<usize> {
-        let mut row = 0;
-        for line in self.lines() {
-            if pos >= line.len() {
-                row += 1;
-                pos -= line.len();
-            } else {
-                break;
-            }
-        }
-        Vec2::new(pos, row)
+    // Iterate forwards over the characters from the position onwards
+    pub fn chars_from(&self, pos: usize) -> impl Iterator<Item=char> + '_ {
+        self.rope.chars_at(pos.min(self.len()))
     }
 
-    pub fn insert(&mut self, pos: usize, c: char) {
-        let (rank, line) = self.pos_to_rank_line(pos).into_tuple();
+    // Iterate backwards over the characters before the position
+    pub fn chars_before(&self, pos: usize) -> impl Iterator<Item=char> + '_ {
+        let mut chars = self.rope.chars_at(pos.min(self.len()));
+        std::iter::from_fn(move || chars.prev())
+    }
 
-        if self.lines.len() <= line {
-            self.lines.push(Vec::new());
+    pub fn char_at(&self, pos: usize) -> Option<char> {
+        if pos < self.len() {
+            Some(self.rope.char(pos))
+        } else if pos == self.len() {
+            Some('\n')
+        } else {
+            None
         }
+    }
 
-        match c {
-            '\n' => {
-                let tail = self.lines[line].split_off(rank);
-                self.lines.insert(line + 1, tail);
-            },
-            c => self.lines[line].insert(rank, c),
+    // Find the (rank, line) location of the given position
+    pub fn pos_loc(&self, pos: usize) -> Vec2<usize> {
+        if pos > self.len() {
+            Vec2::new(pos - self.len() - 1, self.rope.len_lines())
+        } else {
+            let line = self.rope.char_to_line(pos);
+            Vec2::new(pos - self.rope.line_to_char(line), line)
         }
     }
 
-    pub fn insert_line(&mut self, line: usize, s: &str) {
-        self.lines.insert(line, s.chars().collect());
+    // Find the position in the buffer at which the given line begins
+    pub fn line_pos(&self, line: usize) -> usize {
+        if line < self.rope.len_lines() {
+            self.rope.line_to_char(line)
+        } else {
+            self.len() + 1
+        }
     }
 
-    pub fn remove(&mut self, pos: usize) {
-        let (rank, line) = self.pos_to_rank_line(pos).into_tuple();
+    pub fn slice(&self, range: Range<usize>) -> String {
+        self.rope.slice(range).to_string()
+    }
 
-        if self.lines.len() <= line {
-            self.lines.push(Vec::new());
-        }
+    pub fn insert_str(&mut self, pos: usize, s: &str) {
+        self.rope.insert(pos.min(self.len()), s);
+    }
 
-        if self.lines
-            .get(line)
-            .map(|l| rank == l.len())
-            .unwrap_or(false)
-        {
-            if line < self.lines.len() - 1 {
-                let mut old_line = self.lines.remove(line + 1);
-                self.lines[line].append(&mut old_line);
-            }
-        } else if self.lines.get(line).is_some() {
-            self.lines[line].remove(rank);
-        }
+    pub fn remove_range(&mut self, range: Range<usize>) {
+        self.rope.remove(range.start.min(self.len())..range.end.min(self.len()));
     }
 }
 
@@ -106,11 +106,11 @@ impl ToString for Content {
 impl<T: AsRef<str>> From<T> for Content {
     fn from(s: T) -> Self {
         Self {
-            lines: s
+            rope: Rope::from_str(&s
                 .as_ref()
                 .lines()
-                .map(|l| l.chars().collect())
-                .collect(),
+                .collect::<Vec<_>>()
+                .join("\n")),
         }
     }
 }
@@ -144,4 +144,33 @@ mod tests {
         assert_eq!(content.pos_loc(6), Vec2::new(0, 1));
         assert_eq!(content.pos_loc(11), Vec2::new(5, 1));
     }
+
+    #[test]
+    fn edit() {
+        let mut content = Content::from("hello\nworld");
+
+        content.insert_str(5, "\n");
+        assert_eq!(content.lines().len(), 3);
+        assert_eq!(content.line(1).unwrap().get_string(), "\n");
+        assert_eq!(content.line_pos(2), 7);
+
+        content.remove_range(5..7);
+        assert_eq!(content.lines().len(), 1);
+        assert_eq!(content.to_string(), "helloworld\n");
+
+        content.insert_str(content.line_pos(1).min(content.len()), "\ntest");
+        assert_eq!(content.to_string(), "helloworld\ntest\n");
+        assert_eq!(content.char_at(content.len()), Some('\n'));
+        assert_eq!(content.char_at(content.len() + 1), None);
+    }
+
+    #[test]
+    fn only_lf_breaks_lines() {
+        let content = Content::from("a\rb\u{2028}c\x0Bd\x0Ce\u{85}f\u{2029}g\nh");
+
+        assert_eq!(content.lines().len(), 2);
+        assert_eq!(content.line(0).unwrap().len(), 14);
+        assert_eq!(content.line_pos(1), 14);
+        assert_eq!(content.pos_loc(13), Vec2::new(13, 0));
+    }
 }
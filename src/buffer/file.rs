@@ -0,0 +1,118 @@
+use std::{
+    path::{Path, PathBuf},
+    fs::{self, File, OpenOptions, Metadata},
+    io::{self, Write},
+    ffi::OsString,
+    time::SystemTime,
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+// A record of a file's state on disk, used to notice when another program changes it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: u64,
+}
+
+impl Stamp {
+    pub fn new(meta: &Metadata, bytes: &[u8]) -> Self {
+        Self {
+            modified: meta.modified().ok(),
+            len: meta.len(),
+            hash: hash_bytes(bytes),
+        }
+    }
+
+    // Cheaply check whether the file may have changed since the stamp was taken
+    pub fn is_stale(&self, meta: &Metadata) -> bool {
+        meta.modified().ok() != self.modified || meta.len() != self.len
+    }
+
+    pub fn matches(&self, bytes: &[u8]) -> bool {
+        hash_bytes(bytes) == self.hash
+    }
+}
+
+fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+// Find the file that writing to the given path should actually modify, following any symlinks
+pub fn resolve(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+fn sibling(path: &Path, prefix: &str, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(prefix);
+    name.push(path.file_name().unwrap_or_default());
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+// Write the given bytes to a file without ever leaving it partially written. The data is written to a temporary
+// file next to the target, flushed to disk, and then renamed over the target. The original file's permissions
+// and ownership are preserved, and a `~` backup of it is optionally kept.
+pub fn write_atomic(path: &Path, bytes: &[u8], backup: bool) -> io::Result<()> {
+    let target = resolve(path);
+    let old_meta = fs::metadata(&target).ok();
+
+    if backup && old_meta.is_some() {
+        fs::copy(&target, sibling(&target, "", "~"))?;
+    }
+
+    let tmp_path = sibling(&target, ".", &format!(".{}.tmp", std::process::id()));
+    let result = (|| {
+        let mut tmp = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp_path)?;
+        tmp.write_all(bytes)?;
+
+        if let Some(meta) = &old_meta {
+            tmp.set_permissions(meta.permissions())?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                // Changing ownership requires privileges we may not have, in which case the file is owned by us
+                let _ = std::os::unix::fs::fchown(&tmp, Some(meta.uid()), Some(meta.gid()));
+            }
+        }
+
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &target)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    } else if let Some(parent) = target.parent().filter(|p| !p.as_os_str().is_empty()) {
+        // Make sure the rename itself is durable
+        let _ = File::open(parent).and_then(|dir| dir.sync_all());
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn atomic_write() {
+        let dir = std::env::temp_dir().join(format!("zte-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("file.txt");
+
+        write_atomic(&path, b"hello", false).unwrap();
+        write_atomic(&path, b"world", true).unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), b"world");
+        assert_eq!(fs::read(dir.join("file.txt~")).unwrap(), b"hello");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,246 @@
+use std::{fmt, io};
+use super::Content;
+
+const BOM: char = '\u{FEFF}';
+// The number of bytes inspected when guessing whether a file is binary
+const SNIFF_LEN: usize = 8192;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+impl Encoding {
+    // Guess the encoding of the given bytes, returning `None` if they appear to be binary
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        let head = &bytes[..bytes.len().min(SNIFF_LEN)];
+
+        if head.starts_with(&[0xFF, 0xFE]) {
+            return Some(Encoding::Utf16Le);
+        } else if head.starts_with(&[0xFE, 0xFF]) {
+            return Some(Encoding::Utf16Be);
+        }
+
+        // UTF-16 without a BOM is detected by the zero high bytes of ASCII characters
+        if head.len() >= 2 && head.len() % 2 == 0 {
+            let zeroes = |offset| head.iter().skip(offset).step_by(2).filter(|b| **b == 0).count();
+            let (even, odd) = (zeroes(0), zeroes(1));
+            let pairs = head.len() / 2;
+            if odd * 3 > pairs && even == 0 {
+                return Some(Encoding::Utf16Le);
+            } else if even * 3 > pairs && odd == 0 {
+                return Some(Encoding::Utf16Be);
+            }
+        }
+
+        if head.contains(&0) {
+            None
+        } else if std::str::from_utf8(bytes).is_ok() {
+            Some(Encoding::Utf8)
+        } else {
+            Some(Encoding::Latin1)
+        }
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        let utf16 = |f: fn([u8; 2]) -> u16| std::char::decode_utf16(bytes
+            .chunks(2)
+            .map(|c| f([c[0], c.get(1).copied().unwrap_or(0)])))
+            .map(|c| c.unwrap_or(std::char::REPLACEMENT_CHARACTER))
+            .collect();
+
+        match self {
+            Encoding::Utf8 => String::from_utf8_lossy(bytes).into_owned(),
+            Encoding::Utf16Le => utf16(u16::from_le_bytes),
+            Encoding::Utf16Be => utf16(u16::from_be_bytes),
+            Encoding::Latin1 => bytes.iter().map(|b| *b as char).collect(),
+        }
+    }
+
+    pub fn encode(&self, s: &str) -> io::Result<Vec<u8>> {
+        Ok(match self {
+            Encoding::Utf8 => s.as_bytes().to_vec(),
+            Encoding::Utf16Le => s.encode_utf16().flat_map(|c| c.to_le_bytes().to_vec()).collect(),
+            Encoding::Utf16Be => s.encode_utf16().flat_map(|c| c.to_be_bytes().to_vec()).collect(),
+            Encoding::Latin1 => s
+                .chars()
+                .map(|c| if (c as u32) < 0x100 {
+                    Ok(c as u8)
+                } else {
+                    Err(io::Error::new(io::ErrorKind::InvalidData, format!("'{}' cannot be encoded as Latin-1", c)))
+                })
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Encoding::Utf8 => write!(f, "UTF-8"),
+            Encoding::Utf16Le => write!(f, "UTF-16LE"),
+            Encoding::Utf16Be => write!(f, "UTF-16BE"),
+            Encoding::Latin1 => write!(f, "Latin-1"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+
+    pub fn toggled(&self) -> Self {
+        match self {
+            LineEnding::Lf => LineEnding::CrLf,
+            LineEnding::CrLf => LineEnding::Lf,
+        }
+    }
+}
+
+// The details of a file's on-disk representation that are not part of its content
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Format {
+    pub encoding: Encoding,
+    pub line_ending: LineEnding,
+    // The file mixes line endings, which will all become `line_ending` when it is saved
+    pub mixed_endings: bool,
+    pub bom: bool,
+    pub trailing_newline: bool,
+}
+
+impl Format {
+    // Decode the bytes of a file, returning its format and its text. Binary files produce `None`.
+    pub fn decode(bytes: &[u8]) -> Option<(Self, String)> {
+        let encoding = Encoding::detect(bytes)?;
+        let text = encoding.decode(bytes);
+        let (format, _) = Self::detect(&text);
+        let text = text.strip_prefix(BOM).map(|s| s.to_string()).unwrap_or(text);
+        Some((Self { encoding, ..format }, text))
+    }
+
+    // Produce the bytes that should be written to disk for the given content
+    pub fn encode(&self, content: &Content) -> io::Result<Vec<u8>> {
+        self.encoding.encode(&self.apply(content))
+    }
+
+    // Detect the format of the given text, returning it along with the text stripped of any BOM
+    pub fn detect(s: &str) -> (Self, &str) {
+        let (bom, s) = match s.strip_prefix(BOM) {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+
+        // Use whichever line ending is most common
+        let newlines = s.matches('\n').count();
+        let crlfs = s.matches("\r\n").count();
+        let line_ending = if crlfs * 2 > newlines { LineEnding::CrLf } else { LineEnding::Lf };
+
+        (Self {
+            encoding: Encoding::Utf8,
+            line_ending,
+            mixed_endings: crlfs > 0 && crlfs < newlines,
+            bom,
+            trailing_newline: s.ends_with('\n'),
+        }, s)
+    }
+
+    // Produce the text of the content as it should be written to disk
+    pub fn apply(&self, content: &Content) -> String {
+        let mut s = String::with_capacity(content.len() + 16);
+        if self.bom {
+            s.push(BOM);
+        }
+        for c in content.chars().take(content.len()) {
+            match c {
+                '\n' => s.push_str(self.line_ending.as_str()),
+                c => s.push(c),
+            }
+        }
+        if self.trailing_newline {
+            s.push_str(self.line_ending.as_str());
+        }
+        s
+    }
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Self {
+            encoding: Encoding::Utf8,
+            line_ending: LineEnding::Lf,
+            mixed_endings: false,
+            bom: false,
+            trailing_newline: true,
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ", self.encoding)?;
+        match self.line_ending {
+            LineEnding::Lf => write!(f, "LF")?,
+            LineEnding::CrLf => write!(f, "CRLF")?,
+        }
+        if self.mixed_endings {
+            write!(f, " mixed")?;
+        }
+        if self.bom {
+            write!(f, " BOM")?;
+        }
+        if !self.trailing_newline {
+            write!(f, " noeol")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for s in &["hello\r\nworld\r\n", "\u{FEFF}hello\nworld", "", "hello\n\n", "a\r\nb\r\nc"] {
+            let (format, text) = Format::detect(s);
+            assert_eq!(&format.apply(&Content::from(text)), s);
+            assert!(!format.mixed_endings);
+        }
+
+        // Mixed line endings are noticed, even though saving makes them uniform
+        let (format, text) = Format::detect("a\r\nb\nc\r\n");
+        assert!(format.mixed_endings);
+        assert_eq!(format.line_ending, LineEnding::CrLf);
+        assert_eq!(format.apply(&Content::from(text)), "a\r\nb\r\nc\r\n");
+    }
+
+    #[test]
+    fn encodings() {
+        let utf16 = [0xFF, 0xFE, b'h', 0, b'i', 0, b'\n', 0];
+        let (format, text) = Format::decode(&utf16).unwrap();
+        assert_eq!(format.encoding, Encoding::Utf16Le);
+        assert_eq!(text, "hi\n");
+        assert_eq!(format.encode(&Content::from(&text)).unwrap(), utf16);
+
+        let latin1 = [b'c', b'a', b'f', 0xE9];
+        let (format, text) = Format::decode(&latin1).unwrap();
+        assert_eq!(format.encoding, Encoding::Latin1);
+        assert_eq!(text, "café");
+        assert_eq!(format.encode(&Content::from(&text)).unwrap(), latin1);
+
+        assert!(Format::decode(&[0x7F, b'E', b'L', b'F', 0, 0, 1]).is_none());
+    }
+}
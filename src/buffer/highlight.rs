@@ -1,7 +1,4 @@
-use std::{
-    ops::Range,
-    path::Path,
-};
+use std::ops::Range;
 
 #[derive(Default)]
 pub struct Highlights {
@@ -31,15 +28,27 @@ impl Highlights {
             }
         }
     }
+
+    // Whether the character at the position is part of the code itself, rather than a string or comment
+    pub fn is_code(&self, pos: usize) -> bool {
+        !matches!(self.get_at(pos), Region::String | Region::LineComment | Region::MultiComment)
+    }
 }
 
 impl Highlights {
-    pub fn from_file(path: Option<&Path>, src: &str) -> Self {
+    // Regions must be in ascending order and must not overlap
+    pub fn from_regions(regions: Vec<(Range<usize>, Region)>) -> Self {
+        Self { regions }
+    }
+
+    // Highlight the source as the given language, named by its file extension or in full
+    pub fn from_lang(lang: Option<&str>, src: &str) -> Self {
         let indices = src.char_indices().map(|(i, _)| i).collect::<Vec<_>>();
         let byte_to_char_idx = |idx| indices.binary_search(&idx).unwrap_or_else(|i| i);
-        let regions = match path.and_then(|p| p.extension()?.to_str()) {
-            Some("rs") | Some("ron") => RustToken::lexer(src)
+        let regions = match lang {
+            Some("rs") | Some("ron") | Some("rust") => RustToken::lexer(src)
                 .spanned()
+                .map(|(tok, span)| (tok, byte_to_char_idx(span.start)..byte_to_char_idx(span.end)))
                 .map(|(tok, span)| (span, match tok {
                     RustToken::Other => Region::Normal,
                     RustToken::Token(r) => r,
@@ -47,6 +56,7 @@ impl Highlights {
                 .collect(),
             Some("tao") => TaoToken::lexer(src)
                 .spanned()
+                .map(|(tok, span)| (tok, byte_to_char_idx(span.start)..byte_to_char_idx(span.end)))
                 .map(|(tok, span)| (span, match tok {
                     TaoToken::Other => Region::Normal,
                     TaoToken::Token(r) => r,
@@ -60,7 +70,7 @@ impl Highlights {
                     TomlToken::Token(r) => r,
                 }))
                 .collect(),
-            Some("md") => MdToken::lexer(src)
+            Some("md") | Some("markdown") => MdToken::lexer(src)
                 .spanned()
                 .map(|(tok, span)| (tok, byte_to_char_idx(span.start)..byte_to_char_idx(span.end)))
                 .map(|(tok, span)| (span, match tok {
@@ -90,6 +100,22 @@ impl Highlights {
     }
 }
 
+// The ways that a line may be commented out in the language (as a start and end marker), the first of which is
+// preferred when adding comments. Languages that aren't known have none.
+pub fn comment_markers(lang: Option<&str>) -> &'static [(&'static str, &'static str)] {
+    match lang.unwrap_or("") {
+        "rs" | "ron" | "rust" | "glsl" | "vert" | "frag" | "c" | "h" | "cpp" | "hpp" | "cc" | "js" | "ts" | "java"
+        | "go" | "cs" | "swift" | "kt" | "zig" => &[("//", ""), ("/*", "*/")],
+        "css" => &[("/*", "*/")],
+        // Tao and TOML comments run from a `#` to the end of the line, as highlighted above
+        "tao" | "toml" | "sh" | "bash" | "zsh" | "fish" | "py" | "rb" | "pl" | "yaml" | "yml" | "conf" | "cmake"
+        | "nix" | "mk" | "Makefile" | "makefile" | "GNUmakefile" | "Dockerfile" => &[("#", "")],
+        "lua" | "sql" | "hs" => &[("--", "")],
+        "md" | "markdown" | "html" | "xml" | "svg" => &[("<!--", "-->")],
+        _ => &[],
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum Region {
     Normal,
@@ -110,6 +136,7 @@ pub enum Region {
     Error,
     Warning,
     Info,
+    Match,
 }
 
 use logos::Logos;
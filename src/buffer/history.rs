@@ -0,0 +1,582 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    fs,
+    io,
+    time::{Instant, Duration, SystemTime, UNIX_EPOCH},
+};
+use serde_derive::{Serialize, Deserialize};
+use crate::config;
+use super::{
+    shared::CursorId,
+    file::resolve,
+    Content,
+    Cursor,
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Edit {
+    Insert(usize, String),
+    Remove(usize, String),
+}
+
+impl Edit {
+    pub fn inverse(&self) -> Self {
+        match self {
+            Edit::Insert(pos, s) => Edit::Remove(*pos, s.clone()),
+            Edit::Remove(pos, s) => Edit::Insert(*pos, s.clone()),
+        }
+    }
+
+    pub fn apply(&self, content: &mut Content, cursors: &mut HashMap<CursorId, Cursor>) {
+        let (pos, dist) = match self {
+            Edit::Insert(pos, s) => {
+                content.insert_str(*pos, s);
+                (*pos, s.chars().count() as isize)
+            },
+            Edit::Remove(pos, s) => {
+                let len = s.chars().count();
+                content.remove_range(*pos..*pos + len);
+                (*pos, -(len as isize))
+            },
+        };
+        cursors
+            .values_mut()
+            .for_each(|cursor| cursor.shift_relative_to(pos, dist));
+    }
+
+    // Attempt to combine a subsequent edit into this one (i.e: typing or deleting a run of characters)
+    fn merge(&mut self, other: &Self) -> bool {
+        match (self, other) {
+            (Edit::Insert(pos, s), Edit::Insert(other_pos, other_s)) if *pos + s.chars().count() == *other_pos => {
+                s.push_str(other_s);
+                true
+            },
+            // Delete
+            (Edit::Remove(pos, s), Edit::Remove(other_pos, other_s)) if pos == other_pos => {
+                s.push_str(other_s);
+                true
+            },
+            // Backspace
+            (Edit::Remove(pos, s), Edit::Remove(other_pos, other_s)) if *other_pos + other_s.chars().count() == *pos => {
+                s.insert_str(0, other_s);
+                *pos = *other_pos;
+                true
+            },
+            _ => false,
+        }
+    }
+}
+
+// A group of edits that get undone or redone together, along with the cursor positions on either side of it
+#[derive(Clone, Default)]
+pub struct Change {
+    pub edits: Vec<Edit>,
+    pub cursors_before: HashMap<CursorId, Cursor>,
+    pub cursors_after: HashMap<CursorId, Cursor>,
+}
+
+impl Change {
+    pub fn new(cursors_before: HashMap<CursorId, Cursor>) -> Self {
+        Self {
+            edits: Vec::new(),
+            cursors_before,
+            cursors_after: HashMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    pub fn push(&mut self, edit: Edit) {
+        if !self.edits.last_mut().map_or(false, |last| last.merge(&edit)) {
+            self.edits.push(edit);
+        }
+    }
+
+    pub fn redo(&self, content: &mut Content, cursors: &mut HashMap<CursorId, Cursor>) {
+        for edit in self.edits.iter() {
+            edit.apply(content, cursors);
+        }
+    }
+
+    pub fn undo(&self, content: &mut Content, cursors: &mut HashMap<CursorId, Cursor>) {
+        for edit in self.edits.iter().rev() {
+            edit.inverse().apply(content, cursors);
+        }
+    }
+}
+
+pub type NodeId = usize;
+
+pub struct Node {
+    pub change: Change,
+    pub time: Option<Instant>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    // The child that redo will move to
+    active_child: Option<NodeId>,
+}
+
+impl Node {
+    fn new(change: Change, time: Option<Instant>, parent: Option<NodeId>) -> Self {
+        Self {
+            change,
+            time,
+            parent,
+            children: Vec::new(),
+            active_child: None,
+        }
+    }
+}
+
+// A tree of changes. Undoing moves towards the root, redoing moves towards the most recently visited child, and
+// editing after an undo creates a new branch rather than discarding the old one. The root node's change is
+// considered to be permanently applied.
+pub struct History {
+    nodes: HashMap<NodeId, Node>,
+    root: NodeId,
+    current: NodeId,
+    id_counter: NodeId,
+    // The cursors and time at which the next change will begin, once it makes its first edit
+    pending: Option<(HashMap<CursorId, Cursor>, Instant)>,
+    // The current change must not be extended, however recently it was made
+    closed: bool,
+}
+
+impl History {
+    pub fn current(&self) -> NodeId {
+        self.current
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<&Node> {
+        self.nodes.get(&id)
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn insert_child(&mut self, change: Change, time: Option<Instant>) -> NodeId {
+        self.id_counter += 1;
+        let id = self.id_counter;
+        self.nodes.insert(id, Node::new(change, time, Some(self.current)));
+        let parent = self.nodes.get_mut(&self.current).unwrap();
+        parent.children.push(id);
+        parent.active_child = Some(id);
+        self.current = id;
+        self.closed = false;
+        id
+    }
+
+    fn current_is_open(&self) -> bool {
+        !self.closed && self.current != self.root && self.nodes[&self.current].children.is_empty()
+    }
+
+    // Prepare for an edit, extending the current change if it was made recently and starting a new one otherwise.
+    // A new change only enters the tree once it records an edit, so events that edit nothing leave no trace.
+    pub fn begin(&mut self, cursors: &HashMap<CursorId, Cursor>, now: Instant, timeout: Duration) {
+        if self.current_is_open() {
+            let node = self.nodes.get_mut(&self.current).unwrap();
+            if node.time.map_or(false, |t| now.saturating_duration_since(t) < timeout) {
+                node.time = Some(now);
+                self.pending = None;
+                return;
+            }
+        }
+        self.pending = Some((cursors.clone(), now));
+    }
+
+    // Finish the current change, so that the next edit starts a new one even if it follows immediately
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    pub fn record(&mut self, edit: Edit, cursors: &HashMap<CursorId, Cursor>) {
+        if let Some((cursors_before, time)) = self.pending.take() {
+            self.insert_child(Change::new(cursors_before), Some(time));
+        } else if !self.current_is_open() {
+            self.insert_child(Change::new(cursors.clone()), Some(Instant::now()));
+        }
+        self.nodes.get_mut(&self.current).unwrap().change.push(edit);
+    }
+
+    // Move to the parent of the current node, returning the change that must be undone
+    pub fn step_back(&mut self) -> Option<&mut Change> {
+        self.pending = None;
+        if self.current == self.root {
+            return None;
+        }
+        let old = self.current;
+        self.current = self.nodes[&old].parent?;
+        self.nodes.get_mut(&self.current).unwrap().active_child = Some(old);
+        Some(&mut self.nodes.get_mut(&old).unwrap().change)
+    }
+
+    // Move to the active child of the current node, returning the change that must be redone
+    pub fn step_forward(&mut self) -> Option<&mut Change> {
+        self.pending = None;
+        let node = &self.nodes[&self.current];
+        self.current = node.active_child.or_else(|| node.children.last().copied())?;
+        Some(&mut self.nodes.get_mut(&self.current).unwrap().change)
+    }
+
+    // Find the steps required to move from the current node to the target node: the number of undos, followed by
+    // the nodes to be redone in order
+    pub fn path_to(&self, target: NodeId) -> Option<(usize, Vec<NodeId>)> {
+        let ancestors = |mut id: NodeId| {
+            let mut path = vec![id];
+            while let Some(parent) = self.nodes.get(&id).and_then(|n| n.parent) {
+                path.push(parent);
+                id = parent;
+            }
+            path
+        };
+
+        self.nodes.get(&target)?;
+        let from = ancestors(self.current);
+        let mut to = ancestors(target);
+        let common = from.iter().position(|id| to.contains(id))?;
+        to.truncate(to.iter().position(|id| *id == from[common])?);
+        to.reverse();
+        Some((common, to))
+    }
+
+    // Make the given node the one that redo moves to from its parent
+    pub fn select(&mut self, id: NodeId) {
+        if let Some(parent) = self.nodes.get(&id).and_then(|n| n.parent) {
+            self.nodes.get_mut(&parent).unwrap().active_child = Some(id);
+        }
+    }
+
+    // Find the sibling branch of the current node in the given direction
+    pub fn sibling(&self, forward: bool) -> Option<NodeId> {
+        let siblings = &self.nodes[&self.nodes[&self.current].parent?].children;
+        let idx = siblings.iter().position(|id| *id == self.current)?;
+        let len = siblings.len() as isize;
+        Some(siblings[(idx as isize + if forward { 1 } else { -1 }).rem_euclid(len) as usize])
+            .filter(|id| *id != self.current)
+    }
+
+    // Find the node that was most recently edited at the given time
+    pub fn at_time(&self, time: Instant) -> NodeId {
+        self.nodes
+            .iter()
+            .filter_map(|(id, node)| Some((*id, node.time?)))
+            .filter(|(_, t)| *t <= time)
+            .max_by_key(|(_, t)| *t)
+            .map_or(self.root, |(id, _)| id)
+    }
+
+    // Nodes in depth-first order, along with their branch depth (the first child of a node continues at the same
+    // depth, later children branch off one level deeper)
+    pub fn walk(&self) -> Vec<(NodeId, usize)> {
+        let mut nodes = Vec::new();
+        let mut stack = vec![(self.root, 0)];
+        while let Some((id, depth)) = stack.pop() {
+            nodes.push((id, depth));
+            stack.extend(self.nodes[&id].children
+                .iter()
+                .enumerate()
+                .rev()
+                .map(|(i, child)| (*child, depth + (i > 0) as usize)));
+        }
+        nodes
+    }
+
+    // Discard the oldest history until the tree has no more than `max` nodes. Branches that diverge before the
+    // new root are lost.
+    pub fn prune(&mut self, max: usize) {
+        while self.nodes.len() > max && self.root != self.current {
+            let mut new_root = self.current;
+            while let Some(parent) = self.nodes[&new_root].parent.filter(|p| *p != self.root) {
+                new_root = parent;
+            }
+
+            let mut stack = vec![self.root];
+            while let Some(id) = stack.pop() {
+                if id != new_root {
+                    if let Some(node) = self.nodes.remove(&id) {
+                        stack.extend(node.children);
+                    }
+                }
+            }
+
+            let root = self.nodes.get_mut(&new_root).unwrap();
+            root.parent = None;
+            root.change = Change::default();
+            self.root = new_root;
+        }
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        let mut nodes = HashMap::new();
+        nodes.insert(0, Node::new(Change::default(), None, None));
+        Self {
+            nodes,
+            root: 0,
+            current: 0,
+            id_counter: 0,
+            pending: None,
+            closed: false,
+        }
+    }
+}
+
+// On-disk representation of a history tree, used to restore undo history across sessions. Cursors are not
+// persisted since their IDs are only meaningful within a single session.
+#[derive(Serialize, Deserialize)]
+struct SavedNode {
+    id: NodeId,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    active_child: Option<NodeId>,
+    edits: Vec<Edit>,
+    // Milliseconds since the Unix epoch
+    time: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SavedHistory {
+    // The file that the history belongs to, in case of a collision between the hashes of paths
+    file: PathBuf,
+    content_hash: u64,
+    root: NodeId,
+    current: NodeId,
+    id_counter: NodeId,
+    nodes: Vec<SavedNode>,
+}
+
+// A 64-bit FNV-1a hash. Unlike the standard library's hashers, it won't change between builds, so it can be used
+// for data that outlives a session.
+pub(super) fn hash_of(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0xcbf29ce484222325, |hash, b| (hash ^ *b as u64).wrapping_mul(0x100000001b3))
+}
+
+// Files are keyed by their resolved path, so that every path to a file finds the same history and swap file
+pub(super) fn path_hash(file: &Path) -> u64 {
+    hash_of(resolve(file).to_string_lossy().as_bytes())
+}
+
+pub fn content_hash(content: &Content) -> u64 {
+    hash_of(content.to_string().as_bytes())
+}
+
+fn store_path(file: &Path) -> Option<PathBuf> {
+    config::data_path("history", format!("{:016x}.json", path_hash(file)))
+}
+
+// Forget the stored history of a file
+pub fn discard(file: &Path) {
+    if let Some(path) = store_path(file) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+fn instant_to_millis(time: Instant) -> Option<u64> {
+    SystemTime::now()
+        .checked_sub(Instant::now().saturating_duration_since(time))?
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_millis() as u64)
+}
+
+fn millis_to_instant(millis: u64) -> Option<Instant> {
+    let age = SystemTime::now()
+        .duration_since(UNIX_EPOCH + Duration::from_millis(millis))
+        .unwrap_or_default();
+    Instant::now().checked_sub(age)
+}
+
+impl History {
+    // Write the history to the per-file store. `current` is the node corresponding to the content of the file as it
+    // exists on disk, and `content_hash` is the hash of that content.
+    pub fn persist(&self, file: &Path, current: NodeId, content_hash: u64) -> io::Result<()> {
+        if !self.nodes.contains_key(&current) {
+            return Ok(());
+        }
+
+        let saved = SavedHistory {
+            file: resolve(file),
+            content_hash,
+            root: self.root,
+            current,
+            id_counter: self.id_counter,
+            nodes: self.nodes
+                .iter()
+                .map(|(id, node)| SavedNode {
+                    id: *id,
+                    parent: node.parent,
+                    children: node.children.clone(),
+                    active_child: node.active_child,
+                    edits: node.change.edits.clone(),
+                    time: node.time.and_then(instant_to_millis),
+                })
+                .collect(),
+        };
+
+        let path = store_path(file)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no data directory"))?;
+        fs::write(path, serde_json::to_vec(&saved)?)
+    }
+
+    // Load the history of a file from the per-file store, provided it was saved against the same content
+    pub fn restore(file: &Path, content: &Content) -> Option<Self> {
+        let saved = serde_json::from_slice::<SavedHistory>(&fs::read(store_path(file)?).ok()?).ok()?;
+        Self::from_saved(saved, &resolve(file), content)
+    }
+
+    fn from_saved(saved: SavedHistory, file: &Path, content: &Content) -> Option<Self> {
+        if saved.file != file || saved.content_hash != content_hash(content) {
+            return None;
+        }
+
+        let (root, current, id_counter) = (saved.root, saved.current, saved.id_counter);
+        let nodes = saved.nodes
+            .into_iter()
+            .map(|node| (node.id, Node {
+                change: Change {
+                    edits: node.edits,
+                    ..Change::default()
+                },
+                time: node.time.and_then(millis_to_instant),
+                parent: node.parent,
+                children: node.children,
+                active_child: node.active_child,
+            }))
+            .collect::<HashMap<_, _>>();
+
+        // The file may be corrupt, so every reference between nodes must be checked before the tree can be trusted
+        let is_linked = |parent: NodeId, child: NodeId| {
+            matches!(nodes.get(&parent), Some(node) if node.children.contains(&child))
+                && matches!(nodes.get(&child), Some(node) if node.parent == Some(parent))
+        };
+        let is_tree = matches!(nodes.get(&root), Some(node) if node.parent.is_none())
+            && nodes.iter().all(|(id, node)| *id <= id_counter
+                && node.parent.iter().all(|parent| is_linked(*parent, *id))
+                && node.children.iter().all(|child| is_linked(*id, *child))
+                && node.active_child.iter().all(|child| is_linked(*id, *child)));
+        // Every node must also be reachable from the root, or else it is part of a cycle
+        let is_valid = is_tree && nodes.contains_key(&current) && {
+            let mut reached = 0;
+            let mut stack = vec![root];
+            while let Some(id) = stack.pop() {
+                reached += 1;
+                stack.extend(nodes[&id].children.iter().copied());
+            }
+            reached == nodes.len()
+        };
+        if is_valid {
+            Some(Self {
+                nodes,
+                root,
+                current,
+                id_counter,
+                pending: None,
+                closed: false,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undo_redo() {
+        let mut content = Content::from("hello\nworld");
+        let mut cursors = HashMap::new();
+        let mut change = Change::new(cursors.clone());
+
+        for (i, c) in "abc".chars().enumerate() {
+            let edit = Edit::Insert(5 + i, c.to_string());
+            edit.apply(&mut content, &mut cursors);
+            change.push(edit);
+        }
+        let edit = Edit::Remove(0, "h".to_string());
+        edit.apply(&mut content, &mut cursors);
+        change.push(edit);
+
+        assert_eq!(change.edits.len(), 2);
+        assert_eq!(content.to_string(), "elloabc\nworld\n");
+
+        change.undo(&mut content, &mut cursors);
+        assert_eq!(content.to_string(), "hello\nworld\n");
+
+        change.redo(&mut content, &mut cursors);
+        assert_eq!(content.to_string(), "elloabc\nworld\n");
+    }
+
+    #[test]
+    fn branches() {
+        let mut history = History::default();
+        let cursors = HashMap::new();
+        let now = Instant::now();
+
+        history.begin(&cursors, now, Duration::from_secs(0));
+        history.record(Edit::Insert(0, "a".to_string()), &cursors);
+        let a = history.current();
+        history.step_back();
+        history.begin(&cursors, now, Duration::from_secs(0));
+        history.record(Edit::Insert(0, "b".to_string()), &cursors);
+        let b = history.current();
+
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.sibling(true), Some(a));
+        assert_eq!(history.path_to(a), Some((1, vec![a])));
+        assert_eq!(history.walk(), vec![(history.root(), 0), (a, 0), (b, 1)]);
+
+        history.step_back();
+        assert_eq!(history.step_forward().map(|c| c.edits.len()), Some(1));
+        assert_eq!(history.current(), b);
+    }
+
+    #[test]
+    fn restore_checks_links() {
+        let content = Content::from("ab");
+        let node = |id, parent, children: Vec<NodeId>, active_child| SavedNode {
+            id,
+            parent,
+            children,
+            active_child,
+            edits: Vec::new(),
+            time: None,
+        };
+        let saved = |nodes| SavedHistory {
+            file: PathBuf::from("/a"),
+            content_hash: content_hash(&content),
+            root: 0,
+            current: 1,
+            id_counter: 2,
+            nodes,
+        };
+        let restore = |nodes| History::from_saved(saved(nodes), Path::new("/a"), &content).is_some();
+
+        assert!(restore(vec![node(0, None, vec![1], Some(1)), node(1, Some(0), vec![], None)]));
+        // A dangling parent
+        assert!(!restore(vec![node(0, None, vec![1], Some(1)), node(1, Some(5), vec![], None)]));
+        // An active child that isn't a child
+        assert!(!restore(vec![node(0, None, vec![1], Some(2)), node(1, Some(0), vec![], None), node(2, None, vec![], None)]));
+        // A cycle that can't be reached from the root
+        assert!(!restore(vec![
+            node(0, None, vec![], None),
+            node(1, Some(2), vec![2], None),
+            node(2, Some(1), vec![1], None),
+        ]));
+        // Saved for different content
+        assert!(History::from_saved(saved(vec![node(0, None, vec![1], Some(1)), node(1, Some(0), vec![], None)]), Path::new("/a"), &Content::from("x")).is_none());
+    }
+}
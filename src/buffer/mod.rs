@@ -1,6 +1,11 @@
 pub mod shared;
 pub mod content;
+pub mod format;
+pub mod file;
 pub mod highlight;
+pub mod history;
+pub mod swap;
+pub mod search;
 
 // Reexports
 pub use self::{
@@ -13,31 +18,31 @@ use std::{
     io,
 };
 use vek::*;
+use ropey::RopeSlice;
 use crate::Dir;
 
 pub struct Line<'a> {
-    chars: &'a [char],
+    chars: RopeSlice<'a>,
 }
 
 impl<'a> Line<'a> {
     pub fn empty() -> Self {
         Self {
-            chars: &[],
+            chars: RopeSlice::from(""),
         }
     }
 
     pub fn len(&self) -> usize {
-        self.chars.len() + 1
+        self.chars.len_chars() + 1
     }
 
     pub fn get(&self, pos: usize) -> Option<char> {
-        self.chars.get(pos).copied()
+        self.chars.get_char(pos)
     }
 
     pub fn chars(&self) -> impl Iterator<Item=char> + '_ {
         self.chars
-            .iter()
-            .copied()
+            .chars()
             .chain(std::iter::once('\n'))
     }
 
@@ -68,8 +73,8 @@ impl<'a> Line<'a> {
     }
 }
 
-impl<'a> From<&'a [char]> for Line<'a> {
-    fn from(chars: &'a [char]) -> Self {
+impl<'a> From<RopeSlice<'a>> for Line<'a> {
+    fn from(chars: RopeSlice<'a>) -> Self {
         Self { chars }
     }
 }
@@ -154,6 +159,73 @@ impl Default for Config {
     }
 }
 
+impl Config {
+    // Guess how the content is indented from the leading whitespace of its lines. If more lines begin with tabs than
+    // with spaces then tabs are used, otherwise the indentation width is the most common step in indentation
+    // between successive lines. Content without any indentation keeps the default.
+    pub fn detect(content: &Content) -> Self {
+        let mut config = Self::default();
+        let mut tab_lines = 0;
+        let mut space_lines = 0;
+        let mut steps = [0usize; 9];
+        let mut prev_width = 0;
+        for line in content.lines() {
+            let width = line.chars().take_while(|c| *c == ' ').count();
+            match line.get(width) {
+                // Blank lines say nothing about indentation
+                None => continue,
+                Some(c) if c.is_whitespace() && width == 0 => {
+                    tab_lines += (c == '\t') as usize;
+                    continue;
+                },
+                // Continuations of block comments are indented by a single space, whatever the style
+                Some('*') => continue,
+                Some(_) => {},
+            }
+            if width > 0 {
+                space_lines += 1;
+            }
+            if width > prev_width {
+                steps[(width - prev_width).min(8)] += 1;
+            }
+            prev_width = width;
+        }
+
+        if tab_lines > space_lines {
+            config.hard_tabs = true;
+        } else if let Some((step, _)) = steps
+            .iter()
+            .enumerate()
+            .skip(2)
+            .filter(|(_, n)| **n > 0)
+            .max_by_key(|&(step, n)| (*n, std::cmp::Reverse(step)))
+        {
+            config.tab_width = step;
+        }
+        config
+    }
+
+    // Switch to the next of the common indentation styles, as an override of whatever was detected
+    pub fn cycle_indent(&mut self) {
+        let (hard_tabs, tab_width) = match (self.hard_tabs, self.tab_width) {
+            (true, _) => (false, 2),
+            (false, w) if w < 4 => (false, 4),
+            (false, w) if w < 8 => (false, 8),
+            (false, w) => (true, w),
+        };
+        self.hard_tabs = hard_tabs;
+        self.tab_width = tab_width;
+    }
+
+    pub fn describe_indent(&self) -> String {
+        if self.hard_tabs {
+            "tabs".to_string()
+        } else {
+            format!("{} spaces", self.tab_width)
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 pub enum CharKind {
     AlphaNum,
@@ -188,3 +260,17 @@ impl CharKind {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indent_detection() {
+        let detect = |s: &str| Config::detect(&Content::from(s)).describe_indent();
+        assert_eq!(detect("a:\n\tb\n\n\tc\n"), "tabs");
+        assert_eq!(detect("a:\n  b:\n    c\n  d\ne\n"), "2 spaces");
+        assert_eq!(detect("/*\n * a\n */\nfn f() {\n    b\n}\n"), "4 spaces");
+        assert_eq!(detect("a\nb\n"), "4 spaces");
+    }
+}
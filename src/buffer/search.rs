@@ -0,0 +1,184 @@
+use std::ops::Range;
+use regex::{Regex, RegexBuilder};
+use super::Content;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Query {
+    pub text: String,
+    pub case_sensitive: bool,
+    pub whole_word: bool,
+    pub regex: bool,
+}
+
+impl Query {
+    // All modes are implemented by building a regex
+    pub fn compile(&self) -> Result<Regex, regex::Error> {
+        let pattern = if self.regex {
+            self.text.clone()
+        } else {
+            regex::escape(&self.text)
+        };
+        let pattern = if self.whole_word {
+            format!(r"\b(?:{})\b", pattern)
+        } else {
+            pattern
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(!self.case_sensitive)
+            .multi_line(true)
+            .build()
+    }
+
+    // Find the character ranges of every match in the content
+    pub fn find_all(&self, content: &Content) -> Result<Vec<Range<usize>>, regex::Error> {
+        if self.text.is_empty() {
+            return Ok(Vec::new());
+        }
+        Ok(find_with(&self.compile()?, content))
+    }
+
+    // Find the matches lying within the given character range along with the text that should replace each of
+    // them. In regex mode, `$1`-style references to capture groups are expanded.
+    pub fn replacements(&self, content: &Content, within: Range<usize>, with: &str) -> Result<Vec<(Range<usize>, String)>, regex::Error> {
+        if self.text.is_empty() {
+            return Ok(Vec::new());
+        }
+        Ok(self.replacements_with(&self.compile()?, content, within, with))
+    }
+
+    // Like `replacements`, but using a regex already compiled from the query
+    pub fn replacements_with(&self, regex: &Regex, content: &Content, within: Range<usize>, with: &str) -> Vec<(Range<usize>, String)> {
+        let text = content.slice(0..content.len());
+        let captures = regex
+            .captures_iter(&text)
+            .filter(|caps| !caps[0].is_empty())
+            .collect::<Vec<_>>();
+        let ranges = byte_to_char_ranges(&text, captures
+            .iter()
+            .map(|caps| caps.get(0).unwrap().range()));
+        ranges
+            .into_iter()
+            .zip(captures.iter())
+            .filter(|(r, _)| r.start >= within.start && r.end <= within.end)
+            .map(|(r, caps)| {
+                let mut replacement = String::new();
+                if self.regex {
+                    caps.expand(with, &mut replacement);
+                } else {
+                    replacement.push_str(with);
+                }
+                (r, replacement)
+            })
+            .collect()
+    }
+}
+
+// A line containing matches, with the matches given as character ranges within the line
+#[derive(Clone, Debug, PartialEq)]
+pub struct LineMatch {
+    pub line: usize,
+    pub text: String,
+    pub ranges: Vec<Range<usize>>,
+}
+
+pub fn find_with(regex: &Regex, content: &Content) -> Vec<Range<usize>> {
+    let text = content.slice(0..content.len());
+    byte_to_char_ranges(&text, regex
+        .find_iter(&text)
+        .filter(|m| !m.range().is_empty())
+        .map(|m| m.range()))
+}
+
+// Group matches by the line they begin on. Matches spanning several lines are cut off at the end of the first.
+pub fn find_lines_with(regex: &Regex, content: &Content) -> Vec<LineMatch> {
+    let mut lines = Vec::<LineMatch>::new();
+    for m in find_with(regex, content) {
+        let line = content.pos_loc(m.start).y;
+        let line_pos = content.line_pos(line);
+        if lines.last().map_or(true, |l| l.line != line) {
+            let text = content
+                .line(line)
+                .map(|l| l.get_string())
+                .unwrap_or_default();
+            lines.push(LineMatch {
+                line,
+                text: text.trim_end_matches(&['\n', '\r'][..]).to_string(),
+                ranges: Vec::new(),
+            });
+        }
+        let last = lines.last_mut().unwrap();
+        let len = last.text.chars().count();
+        last.ranges.push((m.start - line_pos).min(len)..(m.end - line_pos).min(len));
+    }
+    lines
+}
+
+// Convert ascending byte ranges within the text into character ranges
+pub fn byte_to_char_ranges(text: &str, ranges: impl Iterator<Item=Range<usize>>) -> Vec<Range<usize>> {
+    let mut chars = 0;
+    let mut bytes = 0;
+    let mut to_chars = |byte: usize| {
+        chars += text[bytes..byte].chars().count();
+        bytes = byte;
+        chars
+    };
+    ranges
+        .map(|r| {
+            let start = to_chars(r.start);
+            start..to_chars(r.end)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modes() {
+        let content = Content::from("Foo föo foobar\nfoo");
+        let query = |text: &str, case_sensitive, whole_word, regex| Query {
+            text: text.to_string(),
+            case_sensitive,
+            whole_word,
+            regex,
+        }.find_all(&content).unwrap();
+
+        assert_eq!(query("foo", false, false, false), vec![0..3, 8..11, 15..18]);
+        assert_eq!(query("foo", true, false, false), vec![8..11, 15..18]);
+        assert_eq!(query("foo", false, true, false), vec![0..3, 15..18]);
+        assert_eq!(query("f.o", true, false, true), vec![4..7, 8..11, 15..18]);
+        assert_eq!(query("f.o", true, false, false), Vec::<Range<usize>>::new());
+    }
+
+    #[test]
+    fn lines() {
+        let content = Content::from("ab ab\ncd\nab");
+        let regex = Query { text: "ab".to_string(), ..Query::default() }.compile().unwrap();
+        assert_eq!(find_lines_with(&regex, &content), vec![
+            LineMatch { line: 0, text: "ab ab".to_string(), ranges: vec![0..2, 3..5] },
+            LineMatch { line: 2, text: "ab".to_string(), ranges: vec![0..2] },
+        ]);
+    }
+
+    #[test]
+    fn replacements() {
+        let content = Content::from("let a = f(x);\nlet b = g(y);");
+        let query = Query {
+            text: r"(\w)\((\w)\)".to_string(),
+            regex: true,
+            ..Query::default()
+        };
+
+        let all = query.replacements(&content, 0..content.len(), "$2.$1()").unwrap();
+        assert_eq!(all, vec![(8..12, "x.f()".to_string()), (22..26, "y.g()".to_string())]);
+
+        let within = query.replacements(&content, 14..content.len(), "$2.$1()").unwrap();
+        assert_eq!(within, vec![(22..26, "y.g()".to_string())]);
+
+        // Outside of regex mode, replacements are literal
+        let literal = Query { text: "f(x)".to_string(), ..Query::default() };
+        let literal = literal.replacements(&content, 0..content.len(), "$1").unwrap();
+        assert_eq!(literal, vec![(8..12, "$1".to_string())]);
+    }
+}
@@ -1,17 +1,26 @@
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet},
     sync::Arc,
+    rc::Rc,
     path::{Path, PathBuf},
-    fs::File,
-    io::{self, Read, Write},
+    fs::{self, File, Metadata},
+    io::{self, Read},
+    fmt,
     cmp::PartialEq,
+    ops::{Range, RangeInclusive},
+    cmp::Ordering,
     time::{Instant, Duration},
 };
 #[cfg(feature = "clipboard")]
 use clipboard::{ClipboardContext, ClipboardProvider};
 use vek::*;
-use crate::{Dir, Event};
+use crate::{Dir, Event, SortOrder};
 use super::{
+    highlight::{Highlights, comment_markers},
+    format::Format,
+    file::{write_atomic, resolve, Stamp},
+    swap,
+    history::{self, Edit, History, NodeId},
     Line,
     Config,
     Cursor,
@@ -21,6 +30,9 @@ use super::{
 
 const UNDO_TIMEOUT: Duration = Duration::from_millis(300);
 
+// Pairs of opening and closing brackets
+const BRACKETS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
 pub struct BufferId(pub usize);
 
@@ -29,6 +41,14 @@ pub enum SharedBufferError {
     Io(io::Error),
 }
 
+impl fmt::Display for SharedBufferError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SharedBufferError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
 impl From<io::Error> for SharedBufferError {
     fn from(err: io::Error) ->  Self {
         SharedBufferError::Io(err)
@@ -38,19 +58,39 @@ impl From<io::Error> for SharedBufferError {
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
 pub struct CursorId(usize);
 
-#[derive(Clone)]
 struct State {
     pub content: Content,
     cursors: HashMap<CursorId, Cursor>,
 }
 
+// The number that a line begins with, if any
+fn leading_number(s: &str) -> Option<f64> {
+    let s = s.trim_start();
+    let len = s
+        .char_indices()
+        .find(|&(i, c)| !(c.is_ascii_digit() || c == '.' || (i == 0 && (c == '-' || c == '+'))))
+        .map_or(s.len(), |(i, _)| i);
+    s[..len].parse().ok()
+}
+
+fn sort_lines(mut lines: Vec<String>, order: SortOrder) -> Vec<String> {
+    match order {
+        SortOrder::CaseSensitive => lines.sort(),
+        SortOrder::CaseInsensitive => lines.sort_by_key(|line| line.to_lowercase()),
+        // Lines without a number come first, in their original order
+        SortOrder::Numeric => lines.sort_by(|a, b| leading_number(a)
+            .partial_cmp(&leading_number(b))
+            .unwrap_or(Ordering::Equal)),
+    }
+    lines
+}
+
 impl State {
-    // Make this state align with another according to undo/redo rules (i.e: preserving cursor
-    // positions where possible)
-    fn align_with(&mut self, other: Self) {
-        self.content = other.content;
-        for (id, c) in other.cursors.into_iter() {
-            self.cursors.insert(id, c);
+    // Make this state align with the cursors of another according to undo/redo rules (i.e: preserving
+    // cursor positions where possible)
+    fn align_with(&mut self, cursors: &HashMap<CursorId, Cursor>) {
+        for (id, c) in cursors.iter() {
+            self.cursors.insert(*id, *c);
         }
     }
 }
@@ -59,43 +99,102 @@ const MAX_UNDO_STATES: usize = 256;
 
 pub struct SharedBuffer {
     state: State,
-    past_states: VecDeque<(State, Option<Instant>)>,
-    future_states: Vec<State>,
+    history: History,
     config: Config,
     pub path: Option<PathBuf>,
     cursor_id_counter: usize,
     unsaved: bool,
+    format: Format,
+    binary: bool,
+    read_only: bool,
+    highlights: Option<Rc<Highlights>>,
+    // The language to highlight as, if not the one implied by the path
+    lang: Option<String>,
+    // The history node corresponding to the content of the file on disk
+    disk_node: Option<NodeId>,
+    // A hash of the content of the file on disk, as it was last loaded or saved
+    disk_hash: Option<u64>,
+    disk_stamp: Option<Stamp>,
+    // The key that unsaved content is journalled under while the buffer has no path
+    untitled_key: Option<PathBuf>,
+    // Extra cursors owned by the same editor as a primary cursor. Edits are applied at all of them.
+    cursor_groups: HashMap<CursorId, Vec<CursorId>>,
+    // Rectangular selections being made by primary cursors, as the locations of their anchor and head
+    blocks: HashMap<CursorId, (Vec2<usize>, Vec2<usize>)>,
+}
+
+// A change made to a buffer's file by another program
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DiskChange {
+    Reloaded,
+    // The buffer has unsaved changes, so the user must decide what to do
+    Conflict,
 }
 
 impl SharedBuffer {
     fn pre_edit(&mut self) {
-        let now = Instant::now();
-        if let Some(past_state) = self.past_states
-            .front_mut()
-            .filter(|(_, i)| i.as_ref().map_or(false, |i| now.saturating_duration_since(*i) < UNDO_TIMEOUT))
-        {
-            past_state.1 = Some(now);
-        } else {
-            self.past_states.push_front((self.state.clone(), Some(now)));
-        }
+        self.history.begin(&self.state.cursors, Instant::now(), UNDO_TIMEOUT);
+        self.history.prune(MAX_UNDO_STATES);
+    }
 
-        while self.past_states.len() > MAX_UNDO_STATES {
-            self.past_states.pop_back();
+    // Apply an edit to the content, recording it in the undo history
+    fn edit(&mut self, edit: Edit) {
+        if self.read_only {
+            return;
         }
-        self.future_states.clear();
+        edit.apply(&mut self.state.content, &mut self.state.cursors);
+        self.history.record(edit, &self.state.cursors);
+        self.trigger_mutation();
+    }
+
+    // Undo the current change, returning whether it contained any edits
+    fn step_back(&mut self) -> Option<bool> {
+        let change = self.history.step_back()?;
+        change.cursors_after = self.state.cursors.clone();
+        change.undo(&mut self.state.content, &mut self.state.cursors);
+        self.state.align_with(&change.cursors_before);
+        let is_empty = change.is_empty();
+        self.trigger_mutation();
+        Some(!is_empty)
+    }
+
+    fn step_forward(&mut self) -> Option<bool> {
+        let change = self.history.step_forward()?;
+        change.redo(&mut self.state.content, &mut self.state.cursors);
+        self.state.align_with(&change.cursors_after);
+        let is_empty = change.is_empty();
+        self.trigger_mutation();
+        Some(!is_empty)
     }
 
     fn undo(&mut self) {
-        if let Some((s, _)) = self.past_states.pop_front() {
-            self.future_states.push(self.state.clone());
-            self.state.align_with(s);
-        }
+        while self.step_back() == Some(false) {}
     }
 
     fn redo(&mut self) {
-        if let Some(s) = self.future_states.pop() {
-            self.past_states.push_front((self.state.clone(), None));
-            self.state.align_with(s);
+        while self.step_forward() == Some(false) {}
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    // Undo and redo changes until the buffer is in the state of the given history node
+    pub fn undo_to(&mut self, node: NodeId) {
+        if let Some((undos, redos)) = self.history.path_to(node) {
+            for _ in 0..undos {
+                self.step_back();
+            }
+            for id in redos {
+                self.history.select(id);
+                self.step_forward();
+            }
+        }
+    }
+
+    pub fn undo_branch(&mut self, dir: Dir) {
+        if let Some(sibling) = self.history.sibling(dir.is_forward()) {
+            self.undo_to(sibling);
         }
     }
 
@@ -118,12 +217,70 @@ impl SharedBuffer {
 
     fn trigger_mutation(&mut self) {
         self.unsaved = true;
+        self.highlights = None;
+        let key = match &self.path {
+            Some(path) => path,
+            None => self.untitled_key.get_or_insert_with(swap::untitled_key),
+        };
+        swap::record(key, &self.state.content);
+    }
+
+    // Highlighting is only recomputed after the content changes, not on every render
+    pub fn highlights(&mut self) -> Rc<Highlights> {
+        if self.highlights.is_none() {
+            let src = self.state.content.to_string();
+            self.highlights = Some(Rc::new(Highlights::from_lang(self.lang(), &src)));
+        }
+        self.highlights.clone().unwrap()
+    }
+
+    // The language of the buffer, named as it was given or by the extension of its file (or the file's name, for
+    // files like `Makefile` that have no extension)
+    pub fn lang(&self) -> Option<&str> {
+        self.lang
+            .as_deref()
+            .or_else(|| {
+                let path = self.path.as_ref()?;
+                path.extension().or_else(|| path.file_name())?.to_str()
+            })
+    }
+
+    pub fn set_lang(&mut self, lang: Option<String>) {
+        self.lang = lang;
+        self.highlights = None;
     }
 
     fn remove_cursor(&mut self, id: &CursorId) {
         self.state.cursors.remove(id);
     }
 
+    pub fn cycle_indent(&mut self) {
+        self.config.cycle_indent();
+    }
+
+    pub fn toggle_line_ending(&mut self) {
+        if !self.read_only {
+            self.format.line_ending = self.format.line_ending.toggled();
+            self.unsaved = true;
+        }
+    }
+
+    // Whether saving will make the file's line endings uniform
+    pub fn has_mixed_endings(&self) -> bool {
+        self.format.mixed_endings
+    }
+
+    // A short description of the buffer's on-disk format, for display alongside its title
+    pub fn describe_format(&self) -> String {
+        if self.binary {
+            "binary, read-only".to_string()
+        } else if self.read_only {
+            format!("{}, read-only", self.format)
+        } else {
+            self.format.to_string()
+        }
+    }
+
     pub fn title(&self) -> &str {
         self.path
             .as_ref()
@@ -139,15 +296,16 @@ impl SharedBuffer {
     }
 
     pub fn insert_at(&mut self, pos: usize, c: char) {
-        self.state.content.insert(pos, c);
-        self.state.cursors
-            .values_mut()
-            .for_each(|cursor| cursor.shift_relative_to(pos, 1));
-        self.trigger_mutation();
+        self.edit(Edit::Insert(pos, c.to_string()));
     }
 
     fn insert_line(&mut self, line: usize, s: &str) {
-        self.state.content.insert_line(line, s);
+        let content = &self.state.content;
+        if line < content.lines().len() {
+            self.edit(Edit::Insert(content.line_pos(line), format!("{}\n", s)));
+        } else {
+            self.edit(Edit::Insert(content.len(), format!("\n{}", s)));
+        }
     }
 
     fn backspace(&mut self, id: CursorId) {
@@ -162,11 +320,9 @@ impl SharedBuffer {
     }
 
     fn remove_at(&mut self, pos: usize) {
-        self.state.content.remove(pos);
-        self.state.cursors
-            .values_mut()
-            .for_each(|cursor| cursor.shift_relative_to(pos, -1));
-        self.trigger_mutation();
+        if pos < self.state.content.len() {
+            self.edit(Edit::Remove(pos, self.state.content.slice(pos..pos + 1)));
+        }
     }
 
     pub fn content(&self) -> &Content {
@@ -180,36 +336,257 @@ impl SharedBuffer {
     }
 
     pub fn try_save(&mut self) -> Result<(), io::Error> {
+        if self.read_only {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "buffer is read-only"));
+        }
         if let Some(path) = &self.path {
-            let mut f = File::create(path)?;
-            for c in self.content().chars() {
-                f.write(c.encode_utf8(&mut [0; 4]).as_bytes())?;
-            }
+            let bytes = self.format.encode(self.content())?;
+            write_atomic(path, &bytes, crate::config::CONFIG.backup)?;
             self.unsaved = false;
-            self.path = Some(path.canonicalize()?);
+            self.format.mixed_endings = false;
+            self.disk_stamp = fs::metadata(path).ok().map(|meta| Stamp::new(&meta, &bytes));
+            swap::discard(path);
+            if let Some(key) = self.untitled_key.take() {
+                swap::discard(&key);
+            }
+            self.disk_node = Some(self.history.current());
+            self.disk_hash = Some(history::content_hash(&self.state.content));
+            self.persist_history();
         }
 
         Ok(())
     }
 
-    pub fn open_or_create(path: PathBuf) -> Result<Self, SharedBufferError> {
-        let (content, unsaved) = if let Ok(mut file) = File::open(&path) {
-            let mut buf = String::new();
-            file.read_to_string(&mut buf)?;
-            (Content::from(buf), false)
+    // Save the buffer under a new path. If its file already existed elsewhere, it is moved rather than copied.
+    pub fn save_as(&mut self, path: PathBuf) -> Result<(), io::Error> {
+        let old_path = self.path.replace(path);
+        if let Err(err) = self.try_save() {
+            self.path = old_path;
+            return Err(err);
+        }
+
+        // The old file is moved, so nothing is kept under its path. Both paths are resolved now that the new file
+        // exists, since different paths (like `a.txt` and `./a.txt`, or a symlink and its target) may name one file.
+        let new_path = self.path.as_deref().map(resolve);
+        match old_path.filter(|old| Some(resolve(old)) != new_path) {
+            Some(old_path) => {
+                swap::discard(&old_path);
+                history::discard(&old_path);
+                match fs::remove_file(old_path) {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+                    _ => Ok(()),
+                }
+            },
+            None => Ok(()),
+        }
+    }
+
+    // Check whether the file has been changed by another program, reloading it if the buffer has no unsaved changes
+    pub fn check_disk(&mut self) -> Option<DiskChange> {
+        if self.binary {
+            return None;
+        }
+        let path = self.path.as_ref()?;
+        let stamp = self.disk_stamp.as_ref()?;
+        let meta = fs::metadata(path).ok()?;
+        if !stamp.is_stale(&meta) {
+            return None;
+        }
+
+        let bytes = fs::read(path).ok()?;
+        if stamp.matches(&bytes) {
+            // The file was touched, but its content is the same
+            self.disk_stamp = Some(Stamp::new(&meta, &bytes));
+            None
+        } else if self.unsaved {
+            Some(DiskChange::Conflict)
         } else {
-            (Content::default(), true)
-        };
+            match self.reload() {
+                Ok(()) => Some(DiskChange::Reloaded),
+                Err(err) => {
+                    log::warn!("Failed to reload file: {:?}", err);
+                    None
+                },
+            }
+        }
+    }
 
-        Ok(Self {
-            path: Some(path.canonicalize().unwrap_or(path)),
-            state: State {
-                content,
-                cursors: HashMap::new(),
+    fn read_disk(&self) -> io::Result<(Metadata, Vec<u8>, Format, Content)> {
+        let path = self.path
+            .as_ref()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "buffer has no file"))?;
+        let meta = fs::metadata(path)?;
+        let bytes = fs::read(path)?;
+        let (format, text) = Format::decode(&bytes)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "file is binary"))?;
+        Ok((meta, bytes, format, Content::from(text)))
+    }
+
+    // Replace the content of the buffer with that of the file, as a single undoable change. Only the region that
+    // differs is replaced so that cursors elsewhere keep their positions.
+    pub fn reload(&mut self) -> io::Result<()> {
+        let (meta, bytes, format, content) = self.read_disk()?;
+        self.replace_content(&content);
+
+        self.format = format;
+        self.unsaved = false;
+        self.disk_node = Some(self.history.current());
+        self.disk_hash = Some(history::content_hash(&content));
+        self.disk_stamp = Some(Stamp::new(&meta, &bytes));
+        if let Some(path) = &self.path {
+            swap::discard(path);
+        }
+        Ok(())
+    }
+
+    // Restore content recovered from a swap file. The buffer is left unsaved.
+    pub fn recover(&mut self, content: &Content) {
+        self.replace_content(content);
+    }
+
+    fn replace_content(&mut self, content: &Content) {
+        let old = self.state.content.slice(0..self.state.content.len()).chars().collect::<Vec<_>>();
+        let new = content.slice(0..content.len()).chars().collect::<Vec<_>>();
+        let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+        let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+
+        let removed = old[prefix..old.len() - suffix].iter().collect::<String>();
+        let inserted = new[prefix..new.len() - suffix].iter().collect::<String>();
+        if !removed.is_empty() || !inserted.is_empty() {
+            self.history.begin(&self.state.cursors, Instant::now(), Duration::from_secs(0));
+            if !removed.is_empty() {
+                self.edit(Edit::Remove(prefix, removed));
+            }
+            if !inserted.is_empty() {
+                self.edit(Edit::Insert(prefix, inserted));
+            }
+            self.history.close();
+        }
+    }
+
+    // Replace each of the given ascending, non-overlapping character ranges. All of the replacements form a
+    // single undo step.
+    pub fn replace_ranges(&mut self, replacements: &[(Range<usize>, String)]) {
+        if replacements.is_empty() {
+            return;
+        }
+        self.history.begin(&self.state.cursors, Instant::now(), Duration::from_secs(0));
+        for (range, s) in replacements.iter().rev() {
+            if !range.is_empty() {
+                self.edit(Edit::Remove(range.start, self.state.content.slice(range.clone())));
+            }
+            if !s.is_empty() {
+                self.edit(Edit::Insert(range.start, s.clone()));
+            }
+        }
+        self.history.close();
+    }
+
+    // Accept the file on disk as it is without reloading it, so that the user's changes will replace it on save
+    pub fn keep_changes(&mut self) {
+        if let Some(path) = &self.path {
+            if let (Ok(meta), Ok(bytes)) = (fs::metadata(path), fs::read(path)) {
+                self.disk_stamp = Some(Stamp::new(&meta, &bytes));
+            }
+        }
+    }
+
+    // Produce a unified diff from the file on disk to the content of the buffer
+    pub fn disk_diff(&self) -> io::Result<String> {
+        let (_, _, _, content) = self.read_disk()?;
+        let disk = content.slice(0..content.len());
+        let mine = self.state.content.slice(0..self.state.content.len());
+        Ok(similar::TextDiff::from_lines(&disk, &mine)
+            .unified_diff()
+            .context_radius(2)
+            .header("disk", "buffer")
+            .to_string())
+    }
+
+    // Store the undo history so that it can be restored when the file is next opened
+    fn persist_history(&self) {
+        if let (Some(path), Some(disk_node), Some(disk_hash)) = (&self.path, self.disk_node, self.disk_hash) {
+            if let Err(err) = self.history.persist(path, disk_node, disk_hash) {
+                log::warn!("Failed to persist undo history: {:?}", err);
+            }
+        }
+    }
+
+    // An untitled buffer holding the given bytes, such as those piped to the editor
+    pub fn from_bytes(bytes: &[u8], read_only: bool) -> Self {
+        let (format, text) = Format::decode(bytes)
+            .unwrap_or_else(|| (Format::default(), String::from_utf8_lossy(bytes).into_owned()));
+        let mut this = Self::default();
+        this.state.content = Content::from(text);
+        this.format = format;
+        this.read_only = read_only;
+        // Piped content can't be read again, so it is journalled straight away rather than on its first edit
+        if !read_only {
+            this.trigger_mutation();
+        }
+        this
+    }
+
+    pub fn open_or_create(path: PathBuf) -> Result<Self, SharedBufferError> {
+        let mut binary = false;
+        let mut disk_stamp = None;
+        let (content, format, unsaved) = match File::open(&path) {
+            Ok(mut file) => {
+                let mut bytes = Vec::new();
+                file.read_to_end(&mut bytes)?;
+                disk_stamp = file.metadata().ok().map(|meta| Stamp::new(&meta, &bytes));
+                match Format::decode(&bytes) {
+                    Some((format, text)) => (Content::from(text), format, false),
+                    // Binary files get a read-only view, since saving them would corrupt them
+                    None => {
+                        binary = true;
+                        let text = bytes
+                            .iter()
+                            .map(|b| match *b as char {
+                                c @ ('\n' | '\t') => c,
+                                c if c.is_control() => '.',
+                                c => c,
+                            })
+                            .collect::<String>();
+                        (Content::from(text), Format::default(), false)
+                    },
+                }
             },
-            unsaved,
-            ..Self::default()
-        })
+            // Only a missing file is created, since anything else (like a file that can't be read) would be replaced
+            // by an empty one on save
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (Content::default(), Format::default(), true),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut this = Self::default();
+        if !unsaved {
+            if let Some(history) = History::restore(&path, &content) {
+                this.history = history;
+            }
+            this.disk_node = Some(this.history.current());
+            this.disk_hash = Some(history::content_hash(&content));
+        }
+        this.path = Some(path);
+        this.config = Config::detect(&content);
+        this.state.content = content;
+        this.format = format;
+        this.unsaved = unsaved;
+        this.binary = binary;
+        this.read_only = binary;
+        this.disk_stamp = disk_stamp;
+        Ok(this)
+    }
+}
+
+impl Drop for SharedBuffer {
+    fn drop(&mut self) {
+        self.persist_history();
+        // Unsaved changes are being deliberately thrown away, unless we're crashing
+        if self.unsaved && !std::thread::panicking() {
+            if let Some(key) = self.path.as_ref().or(self.untitled_key.as_ref()) {
+                swap::discard(key);
+            }
+        }
     }
 }
 
@@ -221,11 +598,21 @@ impl Default for SharedBuffer {
                 content: Content::default(),
                 cursors: HashMap::new(),
             },
-            past_states: VecDeque::new(),
-            future_states: Vec::new(),
+            history: History::default(),
             path: None,
             cursor_id_counter: 0,
             unsaved: true,
+            format: Format::default(),
+            binary: false,
+            read_only: false,
+            highlights: None,
+            lang: None,
+            disk_node: None,
+            disk_hash: None,
+            disk_stamp: None,
+            untitled_key: None,
+            cursor_groups: HashMap::new(),
+            blocks: HashMap::new(),
         }
     }
 }
@@ -250,6 +637,10 @@ impl<'a> BufferGuard<'a> {
         self.buffer.content()
     }
 
+    pub fn highlights(&mut self) -> Rc<Highlights> {
+        self.buffer.highlights()
+    }
+
     pub fn title(&self) -> &str {
         self.buffer.title()
     }
@@ -258,6 +649,22 @@ impl<'a> BufferGuard<'a> {
         self.buffer.is_unsaved()
     }
 
+    pub fn has_mixed_endings(&self) -> bool {
+        self.buffer.has_mixed_endings()
+    }
+
+    pub fn describe_format(&self) -> String {
+        self.buffer.describe_format()
+    }
+
+    pub fn cycle_indent(&mut self) {
+        self.buffer.cycle_indent();
+    }
+
+    pub fn toggle_line_ending(&mut self) {
+        self.buffer.toggle_line_ending();
+    }
+
     pub fn len(&self) -> usize {
         self.buffer.content().len()
     }
@@ -290,6 +697,14 @@ impl<'a> BufferGuard<'a> {
         self.buffer.redo();
     }
 
+    pub fn undo_to(&mut self, node: NodeId) {
+        self.buffer.undo_to(node);
+    }
+
+    pub fn undo_branch(&mut self, dir: Dir) {
+        self.buffer.undo_branch(dir);
+    }
+
     pub fn insert(&mut self, c: char) {
         self.buffer.insert(self.cursor_id, c);
         self.cursor_mut().preferred_x = Some(self.cursor_loc().x);
@@ -311,19 +726,16 @@ impl<'a> BufferGuard<'a> {
         self.buffer.delete(self.cursor_id);
     }
 
-    pub fn try_save(&mut self) -> Result<(), io::Error> {
-        self.buffer.try_save()
+    pub fn replace_ranges(&mut self, replacements: &[(Range<usize>, String)]) {
+        self.buffer.replace_ranges(replacements);
     }
 
-    pub fn lines(&self) -> Box<dyn Iterator<Item=Line> + '_> {
-        Box::new((0..self.line_count())
-            .scan(0, move |_, l| self.line(l)))
+    pub fn try_save(&mut self) -> Result<(), io::Error> {
+        self.buffer.try_save()
     }
 
-    pub fn get_string(&self) -> String {
-        let mut s = String::new();
-        self.lines().for_each(|line| s.extend(line.chars()));
-        s
+    pub fn save_as(&mut self, path: PathBuf) -> Result<(), io::Error> {
+        self.buffer.save_as(path)
     }
 
     pub fn path(&self) -> Option<&Path> {
@@ -343,22 +755,14 @@ impl<'a> BufferGuard<'a> {
 		self.pos_loc(self.cursor().pos)
 	}
 
-    pub fn pos_loc(&self, mut pos: usize) -> Vec2<usize> {
-        let mut row = 0;
-        for line in self.lines() {
-            if pos >= line.len() {
-                row += 1;
-                pos -= line.len();
-            } else {
-                break;
-            }
-        }
+    pub fn pos_loc(&self, pos: usize) -> Vec2<usize> {
+        let (rank, row) = self.content().pos_loc(pos).into_tuple();
 
         let mut col = 0;
         match self.line(row) {
             Some(line) => for (p, _) in line.glyphs(self.config()) {
                 match p {
-                    Some(p) if p == pos => break,
+                    Some(p) if p == rank => break,
                     Some(_) => col += 1,
                     None => break,
                 }
@@ -369,9 +773,7 @@ impl<'a> BufferGuard<'a> {
     }
 
     pub fn loc_pos(&self, loc: Vec2<usize>) -> usize {
-        let mut pos = (0..loc.y)
-            .map(|l| self.line(l).map(|l| l.len()).unwrap_or(0))
-            .sum::<usize>();
+        let mut pos = self.content().line_pos(loc.y);
 
         pos += match self.line(loc.y) {
             Some(line) => line
@@ -391,8 +793,70 @@ impl<'a> BufferGuard<'a> {
         let row = self.pos_loc(self.cursor().pos).y;
         if let Some(line) = self.line(row) {
             let s = line.get_string();
-            self.insert_line(row + 1, &s);
+            self.insert_line(row + 1, s.trim_end_matches('\n'));
+        }
+    }
+
+    // Comment out the selected lines, or uncomment them if they are all commented already. Markers are added at the
+    // indentation of the least indented line, so that they line up. Blank lines are left alone. Returns `false` if
+    // the language has no comment syntax.
+    pub fn toggle_comment(&mut self) -> bool {
+        let markers = comment_markers(self.buffer.lang());
+        let (start, end) = match markers.first() {
+            Some(marker) => *marker,
+            None => return false,
+        };
+
+        let lines = self.selected_lines()
+            .into_iter()
+            .filter_map(|l| Some((self.content().line_pos(l), self.line(l)?.get_string().trim_end_matches('\n').to_string())))
+            .filter(|(_, s)| !s.trim().is_empty())
+            .collect::<Vec<_>>();
+        let marker_of = |s: &str| {
+            let s = s.trim();
+            markers
+                .iter()
+                .find(|(start, end)| s.len() >= start.len() + end.len() && s.starts_with(start) && s.ends_with(end))
+        };
+
+        let mut replacements = Vec::new();
+        if !lines.is_empty() && lines.iter().all(|(_, s)| marker_of(s).is_some()) {
+            for (pos, s) in lines.iter() {
+                let (start, end) = marker_of(s).unwrap();
+                let chars = s.chars().collect::<Vec<_>>();
+                let indent = chars.iter().take_while(|c| c.is_whitespace()).count();
+                let len = s.trim_end().chars().count();
+
+                // A single space is removed along with each marker
+                let mut start_end = indent + start.chars().count();
+                if chars.get(start_end) == Some(&' ') {
+                    start_end += 1;
+                }
+                replacements.push((pos + indent..pos + start_end, String::new()));
+                if !end.is_empty() {
+                    let mut end_start = len - end.chars().count();
+                    if end_start > start_end && chars[end_start - 1] == ' ' {
+                        end_start -= 1;
+                    }
+                    replacements.push((pos + end_start.max(start_end)..pos + len, String::new()));
+                }
+            }
+        } else {
+            let indent = lines
+                .iter()
+                .map(|(_, s)| s.chars().take_while(|c| c.is_whitespace()).count())
+                .min()
+                .unwrap_or(0);
+            for (pos, s) in lines.iter() {
+                replacements.push((pos + indent..pos + indent, format!("{} ", start)));
+                if !end.is_empty() {
+                    let len = s.trim_end().chars().count();
+                    replacements.push((pos + len..pos + len, format!(" {}", end)));
+                }
+            }
         }
+        self.replace_ranges(&replacements);
+        true
     }
 
     pub fn insert_str(&mut self, s: &str) {
@@ -407,6 +871,16 @@ impl<'a> BufferGuard<'a> {
         }
     }
 
+    // Move to a line and a column counted in characters (not glyphs), keeping only this cursor
+    pub fn go_to_loc(&mut self, loc: Vec2<usize>) {
+        let line = loc.y.min(self.line_count().saturating_sub(1));
+        let line_len = self.line(line).map_or(1, |l| l.len());
+        let pos = self.content().line_pos(line) + loc.x.min(line_len.saturating_sub(1));
+        self.clear_extra_cursors();
+        self.cursor_mut().go_to(pos);
+        self.cursor_mut().preferred_x = None;
+    }
+
     pub fn cursor_set(&mut self, loc: Vec2<usize>) {
         self.cursor_mut().pos = self.loc_pos(loc);
     }
@@ -480,48 +954,133 @@ impl<'a> BufferGuard<'a> {
                     }
                 }
             },
+            // Jump over the next block
             Dir::Down => {
-                let old_pos = self.cursor().pos;
-                let (left, right) = loop {
-                    match self.get_next_char(Dir::Right) {
-                        Some('{') => {
-                            self.cursor_move(Dir::Right, 1);
-                            break ('{', '}');
-                        },
-                        Some(_) => if !self.cursor_move(Dir::Right, 1) {
-                            return
-                        } else {},
-                        None => {
-                            // Cancel
-                            self.cursor_mut().pos = old_pos;
-                            return;
-                        },
-                    }
-                };
-
-                let mut balance = 0;
-                loop {
-                    match self.get_next_char(Dir::Right) {
-                        Some(c) if c == right => if balance == 0 {
-                            self.cursor_move(Dir::Right, 1);
-                            break
-                        } else {
-                            balance -= 1;
-                        },
-                        Some(c) if c == left => balance += 1,
-                        Some(_) => {},
-                        None => {
-                            // Cancel
-                            self.cursor_mut().pos = old_pos;
-                            return;
-                        },
-                    }
-                    if !self.cursor_move(Dir::Right, 1) {
-                        return;
-                    }
+                let highlights = self.highlights();
+                let pos = self.cursor().pos;
+                let start = self.content()
+                    .chars_from(pos)
+                    .enumerate()
+                    .find(|&(i, c)| c == '{' && highlights.is_code(pos + i))
+                    .map(|(i, _)| pos + i);
+                if let Some(end) = start.and_then(|start| self.bracket_partner(start)) {
+                    self.cursor_mut().pos = end + 1;
+                }
+            },
+            // Jump back over the previous block
+            Dir::Up => {
+                let highlights = self.highlights();
+                let pos = self.cursor().pos;
+                let end = self.content()
+                    .chars_before(pos)
+                    .enumerate()
+                    .find(|&(i, c)| c == '}' && highlights.is_code(pos - i - 1))
+                    .map(|(i, _)| pos - i - 1);
+                if let Some(start) = end.and_then(|end| self.bracket_partner(end)) {
+                    self.cursor_mut().pos = start;
                 }
             },
-            Dir::Up => {},
+        }
+    }
+
+    // Find the partner of the bracket at the given position. Brackets within strings and comments are ignored.
+    pub fn bracket_partner(&mut self, pos: usize) -> Option<usize> {
+        let highlights = self.highlights();
+        let c = self.content().char_at(pos).filter(|_| pos < self.len() && highlights.is_code(pos))?;
+        let &(open, close) = BRACKETS.iter().find(|(open, close)| c == *open || c == *close)?;
+
+        let mut depth = 0;
+        let mut step = |i: usize, c: char| {
+            if c == open && highlights.is_code(i) {
+                depth += 1;
+            } else if c == close && highlights.is_code(i) {
+                depth -= 1;
+            }
+            depth == 0
+        };
+        if c == open {
+            self.content()
+                .chars_from(pos)
+                .enumerate()
+                .find(|&(i, c)| step(pos + i, c))
+                .map(|(i, _)| pos + i)
+        } else {
+            self.content()
+                .chars_before(pos + 1)
+                .enumerate()
+                .find(|&(i, c)| step(pos - i, c))
+                .map(|(i, _)| pos - i)
+        }
+    }
+
+    // Find the bracket under or just before the cursor, and its partner
+    pub fn matching_bracket(&mut self) -> Option<(usize, usize)> {
+        let pos = self.cursor().pos;
+        self.bracket_partner(pos)
+            .map(|partner| (pos, partner))
+            .or_else(|| {
+                let before = pos.checked_sub(1)?;
+                self.bracket_partner(before).map(|partner| (before, partner))
+            })
+    }
+
+    // Find the nearest unmatched bracket that encloses the position, searching in the given direction
+    pub fn enclosing_bracket(&mut self, pos: usize, dir: Dir) -> Option<usize> {
+        let highlights = self.highlights();
+        let mut depths = [0; BRACKETS.len()];
+        let mut step = |i: usize, c: char, forward: bool| {
+            let (idx, is_open) = BRACKETS
+                .iter()
+                .enumerate()
+                .find_map(|(idx, &(open, close))| if c == open {
+                    Some((idx, true))
+                } else if c == close {
+                    Some((idx, false))
+                } else {
+                    None
+                })?;
+            if !highlights.is_code(i) {
+                None
+            } else if is_open == forward {
+                depths[idx] += 1;
+                None
+            } else if depths[idx] > 0 {
+                depths[idx] -= 1;
+                None
+            } else {
+                Some(i)
+            }
+        };
+        match dir {
+            Dir::Up | Dir::Left => self.content()
+                .chars_before(pos)
+                .enumerate()
+                .find_map(|(i, c)| step(pos - i - 1, c, false)),
+            Dir::Down | Dir::Right => self.content()
+                .chars_from(pos)
+                .enumerate()
+                .find_map(|(i, c)| step(pos + i, c, true)),
+        }
+    }
+
+    // Move the cursor to the other side of the matching bracket
+    pub fn jump_to_matching_bracket(&mut self) {
+        let pos = self.cursor().pos;
+        match self.matching_bracket() {
+            // The cursor stays on the same side of the bracket, before or after it
+            Some((bracket, partner)) if bracket == pos => self.cursor_mut().pos = partner,
+            Some((_, partner)) => self.cursor_mut().pos = partner + 1,
+            None => {},
+        }
+    }
+
+    // Move the cursor to just outside the block that encloses it
+    pub fn jump_out_of_block(&mut self, dir: Dir) {
+        let pos = self.cursor().pos;
+        match (self.enclosing_bracket(pos, dir), dir) {
+            (Some(bracket), Dir::Up | Dir::Left) => self.cursor_mut().pos = bracket,
+            (Some(bracket), Dir::Down | Dir::Right) => self.cursor_mut().pos = bracket + 1,
+            (None, _) => {},
         }
     }
 
@@ -571,6 +1130,11 @@ impl<'a> BufferGuard<'a> {
         }
     }
 
+    // Find the position of the newline at the end of the given line
+    pub fn line_end(&self, line: usize) -> usize {
+        self.content().line_pos(line) + self.line(line).map_or(0, |line| line.len() - 1)
+    }
+
     // Find the position in the buffer at which the given line begins (i.e: initial indentation ends)
     pub fn line_start(&self, line: usize) -> Option<usize> {
         let pos = self.loc_pos(Vec2::new(0, line));
@@ -593,21 +1157,537 @@ impl<'a> BufferGuard<'a> {
         }
     }
 
+    // The lines touched by the editor's cursors, grouped into blocks of consecutive lines
+    fn selected_blocks(&self) -> Vec<RangeInclusive<usize>> {
+        let mut blocks = Vec::<RangeInclusive<usize>>::new();
+        for l in self.selected_lines() {
+            match blocks.last_mut() {
+                Some(block) if *block.end() + 1 == l => *block = *block.start()..=l,
+                _ => blocks.push(l..=l),
+            }
+        }
+        blocks
+    }
+
+    // The text of each of the lines, without their newlines
+    fn line_strings(&self, lines: RangeInclusive<usize>) -> Vec<String> {
+        lines
+            .filter_map(|l| Some(self.line(l)?.get_string().trim_end_matches('\n').to_string()))
+            .collect()
+    }
+
+    // The range covered by the lines, excluding the newline after the last of them
+    fn lines_range(&self, lines: &RangeInclusive<usize>) -> Range<usize> {
+        self.content().line_pos(*lines.start())..self.line_end(*lines.end())
+    }
+
+    // The index of the block containing each of the editor's cursors, found by the start of its selection
+    fn cursor_blocks(&self, blocks: &[RangeInclusive<usize>]) -> Vec<(CursorId, usize)> {
+        self.cursor_ids()
+            .into_iter()
+            .filter_map(|id| {
+                let cursor = self.buffer.cursor(id);
+                let line = self.pos_loc(cursor.base.min(cursor.pos)).y;
+                Some((id, blocks.iter().position(|block| block.contains(&line))?))
+            })
+            .collect()
+    }
+
+    // Replace each block of selected lines with new lines as a single undo step, then select the new lines
+    fn transform_lines(&mut self, f: impl Fn(Vec<String>) -> Vec<String>) {
+        let blocks = self.selected_blocks();
+        let replacements = blocks
+            .iter()
+            .map(|block| (self.lines_range(block), f(self.line_strings(block.clone())).join("\n")))
+            .collect::<Vec<_>>();
+
+        let mut offset = 0isize;
+        let selections = replacements
+            .iter()
+            .map(|(range, s)| {
+                let start = (range.start as isize + offset) as usize;
+                let len = s.chars().count();
+                offset += len as isize - range.len() as isize;
+                start..start + len
+            })
+            .collect::<Vec<_>>();
+
+        let cursor_blocks = self.cursor_blocks(&blocks);
+        self.replace_ranges(&replacements);
+        for (id, i) in cursor_blocks {
+            let cursor = self.buffer.cursor_mut(id);
+            cursor.base = selections[i].start;
+            cursor.pos = selections[i].end;
+            cursor.preferred_x = None;
+        }
+    }
+
+    // Swap each block of selected lines with the line above or below it, carrying the cursors along
+    pub fn move_lines(&mut self, dir: Dir) {
+        let blocks = self.selected_blocks();
+        let up = match dir {
+            Dir::Up if blocks.first().map_or(false, |block| *block.start() > 0) => true,
+            Dir::Down if blocks.last().map_or(false, |block| *block.end() + 1 < self.line_count()) => false,
+            _ => return,
+        };
+
+        let mut shifts = Vec::new();
+        let replacements = blocks
+            .iter()
+            .map(|block| {
+                let lines = self.line_strings(block.clone());
+                if up {
+                    let above = self.line_strings(block.start() - 1..=block.start() - 1).join("");
+                    shifts.push(-(above.chars().count() as isize + 1));
+                    (self.lines_range(&(block.start() - 1..=*block.end())), lines.join("\n") + "\n" + &above)
+                } else {
+                    let below = self.line_strings(block.end() + 1..=block.end() + 1).join("");
+                    shifts.push(below.chars().count() as isize + 1);
+                    (self.lines_range(&(*block.start()..=block.end() + 1)), below + "\n" + &lines.join("\n"))
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let cursors = self.cursor_blocks(&blocks)
+            .into_iter()
+            .map(|(id, i)| (id, *self.buffer.cursor(id), shifts[i]))
+            .collect::<Vec<_>>();
+        self.replace_ranges(&replacements);
+        for (id, old, shift) in cursors {
+            let cursor = self.buffer.cursor_mut(id);
+            cursor.base = (old.base as isize + shift) as usize;
+            cursor.pos = (old.pos as isize + shift) as usize;
+        }
+    }
+
+    // Join each block of selected lines (or a lone line and the line after it) into a single line, collapsing the
+    // whitespace between them
+    pub fn join_lines(&mut self) {
+        let line_count = self.line_count();
+        let blocks = self.selected_blocks()
+            .into_iter()
+            .map(|block| if block.start() == block.end() && block.end() + 1 < line_count {
+                *block.start()..=block.end() + 1
+            } else {
+                block
+            })
+            .collect::<Vec<_>>();
+
+        let mut joins = Vec::new();
+        let replacements = blocks
+            .iter()
+            .map(|block| {
+                let mut joined = String::new();
+                for (i, line) in self.line_strings(block.clone()).into_iter().enumerate() {
+                    let line = if i == 0 { &line[..] } else { line.trim_start() };
+                    joined.truncate(joined.trim_end().len());
+                    let space = i > 0
+                        && !joined.is_empty()
+                        && !line.is_empty()
+                        && !joined.ends_with(['(', '['])
+                        && !line.starts_with([')', ']', ',', ';', '.']);
+                    if space {
+                        joined.push(' ');
+                    }
+                    joins.push(joined.chars().count());
+                    joined.push_str(line);
+                }
+                (self.lines_range(block), joined)
+            })
+            .collect::<Vec<_>>();
+
+        // The cursor of each block is left where its last two lines were joined
+        let mut offset = 0isize;
+        let mut join_positions = Vec::new();
+        let mut joins = joins.into_iter();
+        for (block, (range, s)) in blocks.iter().zip(replacements.iter()) {
+            let last_join = joins.by_ref().take(block.clone().count()).last().unwrap_or(0);
+            join_positions.push((range.start as isize + offset) as usize + last_join);
+            offset += s.chars().count() as isize - range.len() as isize;
+        }
+
+        let cursor_blocks = self.cursor_blocks(&blocks);
+        self.replace_ranges(&replacements);
+        for (id, i) in cursor_blocks {
+            let cursor = self.buffer.cursor_mut(id);
+            cursor.go_to(join_positions[i]);
+            cursor.preferred_x = None;
+        }
+    }
+
+    // Remove one level of indentation from each of the selected lines
+    pub fn dedent(&mut self) {
+        let tab_width = self.config().tab_width;
+        let replacements = self.selected_lines()
+            .into_iter()
+            .filter_map(|l| {
+                let line = self.line(l)?;
+                let len = match line.get(0)? {
+                    '\t' => 1,
+                    // Spaces are removed back to the previous tab stop
+                    ' ' => (line.chars().take_while(|c| *c == ' ').count() - 1) % tab_width + 1,
+                    _ => return None,
+                };
+                let pos = self.content().line_pos(l);
+                Some((pos..pos + len, String::new()))
+            })
+            .collect::<Vec<_>>();
+        self.replace_ranges(&replacements);
+    }
+
+    // Derive the indentation of each of the selected lines from how deeply it is nested within brackets. Lines that
+    // begin with closing brackets belong to the level outside of them. Blank lines are left alone.
+    pub fn reindent(&mut self) {
+        let lines = self.selected_lines();
+        let last = match lines.last() {
+            Some(last) => *last,
+            None => return,
+        };
+        let unit = if self.config().hard_tabs {
+            "\t".to_string()
+        } else {
+            " ".repeat(self.config().tab_width)
+        };
+
+        // The nesting depth at the start of each line, up to the last selected one
+        let highlights = self.highlights();
+        let mut depths = Vec::new();
+        let mut depth = 0usize;
+        for l in 0..=last {
+            depths.push(depth);
+            let pos = self.content().line_pos(l);
+            for (i, c) in self.line(l).iter().flat_map(|line| line.chars()).enumerate() {
+                if !highlights.is_code(pos + i) {
+                    continue;
+                } else if BRACKETS.iter().any(|(open, _)| c == *open) {
+                    depth += 1;
+                } else if BRACKETS.iter().any(|(_, close)| c == *close) {
+                    depth = depth.saturating_sub(1);
+                }
+            }
+        }
+
+        let replacements = lines
+            .into_iter()
+            .filter_map(|l| {
+                let s = self.line(l)?.get_string();
+                let indent = s.chars().take_while(|c| c.is_whitespace() && *c != '\n').count();
+                let body = s[s.char_indices().nth(indent)?.0..].trim_end_matches('\n');
+                if body.is_empty() {
+                    return None;
+                }
+                let closers = body
+                    .chars()
+                    .take_while(|c| BRACKETS.iter().any(|(_, close)| c == close))
+                    .count();
+                let new_indent = unit.repeat(depths[l].saturating_sub(closers));
+                let pos = self.content().line_pos(l);
+                Some((pos..pos + indent, new_indent))
+                    .filter(|(_, new_indent)| s.chars().take(indent).ne(new_indent.chars()))
+            })
+            .collect::<Vec<_>>();
+        self.replace_ranges(&replacements);
+    }
+
+    // All of the cursors owned by this guard's editor, primary first
+    pub fn cursor_ids(&self) -> Vec<CursorId> {
+        std::iter::once(self.cursor_id)
+            .chain(self.buffer.cursor_groups.get(&self.cursor_id).into_iter().flatten().copied())
+            .collect()
+    }
+
+    pub fn cursors(&self) -> Vec<Cursor> {
+        self.cursor_ids()
+            .into_iter()
+            .map(|id| *self.buffer.cursor(id))
+            .collect()
+    }
+
+    pub fn has_extra_cursors(&self) -> bool {
+        self.buffer.cursor_groups.get(&self.cursor_id).map_or(false, |group| !group.is_empty())
+    }
+
+    fn add_cursor(&mut self, cursor: Cursor) {
+        if self.cursors().iter().any(|c| c.pos == cursor.pos) {
+            return;
+        }
+        let id = self.buffer.insert_cursor(cursor);
+        self.buffer.cursor_groups.entry(self.cursor_id).or_default().push(id);
+    }
+
+    pub fn clear_extra_cursors(&mut self) {
+        for id in self.buffer.cursor_groups.remove(&self.cursor_id).unwrap_or_default() {
+            self.buffer.remove_cursor(&id);
+        }
+    }
+
+    // The corners of the rectangular selection being made, if any
+    pub fn block(&self) -> Option<(Vec2<usize>, Vec2<usize>)> {
+        self.buffer.blocks.get(&self.cursor_id).copied()
+    }
+
+    pub fn clear_block(&mut self) {
+        self.buffer.blocks.remove(&self.cursor_id);
+    }
+
+    // Extend the rectangular selection in the given direction. The block is made up of one cursor per line, so
+    // edits made afterwards apply column-wise.
+    pub fn block_select(&mut self, dir: Dir) {
+        let (anchor, mut head) = self.block().unwrap_or_else(|| {
+            let loc = self.cursor_loc();
+            (loc, loc)
+        });
+        match dir {
+            Dir::Left => head.x = head.x.saturating_sub(1),
+            Dir::Right => head.x += 1,
+            Dir::Up => head.y = head.y.saturating_sub(1),
+            Dir::Down => head.y = (head.y + 1).min(self.line_count().saturating_sub(1)),
+        }
+
+        let (left, right) = (anchor.x.min(head.x), anchor.x.max(head.x));
+        let block_cursor = |this: &Self, y| {
+            let start = this.loc_pos(Vec2::new(left, y));
+            // Include the whole of any glyph that the right edge of the block falls within (i.e: a tab)
+            let end = if right > left {
+                (this.loc_pos(Vec2::new(right - 1, y)) + 1).min(this.line_end(y)).max(start)
+            } else {
+                start
+            };
+            let (base, pos) = if head.x >= anchor.x { (start, end) } else { (end, start) };
+            Cursor { base, pos, preferred_x: Some(head.x) }
+        };
+
+        // The primary cursor follows the head of the block
+        self.clear_extra_cursors();
+        *self.cursor_mut() = block_cursor(self, head.y);
+        for y in anchor.y.min(head.y)..=anchor.y.max(head.y) {
+            if y != head.y {
+                self.add_cursor(block_cursor(self, y));
+            }
+        }
+        self.buffer.blocks.insert(self.cursor_id, (anchor, head));
+    }
+
+    // Perform an action at each of the editor's cursors in turn
+    pub fn for_each_cursor(&mut self, mut f: impl FnMut(&mut Self)) {
+        let primary = self.cursor_id;
+        for id in self.cursor_ids() {
+            self.cursor_id = id;
+            f(self);
+        }
+        self.cursor_id = primary;
+        self.merge_cursors();
+    }
+
+    // Remove extra cursors that have ended up in the same place as another
+    fn merge_cursors(&mut self) {
+        let mut seen = HashSet::new();
+        let merged = self.cursor_ids()
+            .into_iter()
+            .filter(|id| !seen.insert(self.buffer.cursor(*id).pos))
+            .collect::<Vec<_>>();
+        for id in merged {
+            if let Some(group) = self.buffer.cursor_groups.get_mut(&self.cursor_id) {
+                group.retain(|other| *other != id);
+            }
+            self.buffer.remove_cursor(&id);
+        }
+    }
+
+    fn spans_lines(&self, id: CursorId) -> bool {
+        let cursor = self.buffer.cursor(id);
+        self.pos_loc(cursor.base).y != self.pos_loc(cursor.pos).y
+    }
+
+    // The lines touched by any of the editor's cursors, in order. A selection that ends at the start of a line doesn't
+    // touch that line, so that selecting whole lines doesn't take in the one after them.
+    pub fn selected_lines(&self) -> Vec<usize> {
+        let mut lines = self.cursors()
+            .into_iter()
+            .flat_map(|c| {
+                let start = self.pos_loc(c.base.min(c.pos)).y;
+                let end = self.pos_loc(c.base.max(c.pos));
+                start..=if end.x == 0 && end.y > start { end.y - 1 } else { end.y }
+            })
+            .collect::<Vec<_>>();
+        lines.sort_unstable();
+        lines.dedup();
+        lines
+    }
+
+    #[cfg(feature = "clipboard")]
+    fn selections(&mut self) -> Vec<String> {
+        let mut selections = Vec::new();
+        self.for_each_cursor(|b| selections.push(b.selection().collect()));
+        selections
+    }
+
+    // Add a cursor on the line above or below the furthest cursor in that direction
+    pub fn add_cursor_vertical(&mut self, dir: Dir) {
+        let cursors = self.cursors();
+        let furthest = match dir {
+            Dir::Up => cursors.iter().min_by_key(|c| c.pos),
+            Dir::Down => cursors.iter().max_by_key(|c| c.pos),
+            _ => return,
+        }.copied().unwrap();
+
+        let loc = self.pos_loc(furthest.pos);
+        let x = furthest.preferred_x.unwrap_or(loc.x);
+        let y = match dir {
+            Dir::Up if loc.y > 0 => loc.y - 1,
+            Dir::Down if loc.y + 1 < self.line_count() => loc.y + 1,
+            _ => return,
+        };
+        let pos = self.loc_pos(Vec2::new(x, y));
+        self.add_cursor(Cursor { base: pos, pos, preferred_x: Some(x) });
+    }
+
+    // Select the word under the cursor or, if something is already selected, add a cursor selecting the next
+    // occurrence of it
+    pub fn add_next_occurrence(&mut self) {
+        if !self.cursor().is_reaching() {
+            let kind = match self.get_next_char(Dir::Right).map(CharKind::from_char) {
+                Some(kind @ Some(_)) => kind,
+                _ => return,
+            };
+            while self.get_next_char(Dir::Left).map(CharKind::from_char) == Some(kind) && self.cursor_move(Dir::Left, 1) {}
+            self.cursor_mut().reset_base();
+            while self.get_next_char(Dir::Right).map(CharKind::from_char) == Some(kind) && self.cursor_move(Dir::Right, 1) {}
+            return;
+        }
+
+        let last = self.cursors().last().copied().unwrap();
+        let needle = self.content().slice(last.base.min(last.pos)..last.base.max(last.pos)).chars().collect::<Vec<_>>();
+        let text = self.content().chars().take(self.len()).collect::<Vec<_>>();
+
+        // Search forward from the last cursor, wrapping around to the start of the buffer
+        let from = last.base.max(last.pos);
+        let found = (from..text.len())
+            .chain(0..from)
+            .find(|i| text[*i..].starts_with(&needle) && !self.cursors().iter().any(|c| c.base.min(c.pos) == *i));
+        if let Some(start) = found {
+            self.add_cursor(Cursor { base: start, pos: start + needle.len(), preferred_x: None });
+        }
+    }
+
+    // Turn a selection spanning several lines into one selection per line
+    pub fn split_selection(&mut self) {
+        let cursor = *self.cursor();
+        let (start, end) = (cursor.base.min(cursor.pos), cursor.base.max(cursor.pos));
+        let (first, last) = (self.pos_loc(start).y, self.pos_loc(end).y);
+        if first == last {
+            return;
+        }
+
+        for l in first..=last {
+            let (base, pos) = (start.max(self.content().line_pos(l)), end.min(self.line_end(l)));
+            if l == first {
+                *self.cursor_mut() = Cursor { base, pos, preferred_x: None };
+            } else {
+                self.add_cursor(Cursor { base, pos, preferred_x: None });
+            }
+        }
+    }
+
     pub fn handle(&mut self, event: Event) -> Result<(), Event> {
         match event {
             // Do not mutate
-            Event::CursorMove(dir, reach) => return Ok(self.do_cursor_movement(dir, reach, |b| { b.cursor_move(dir, 1); })),
-            Event::CursorJump(dir, reach) => return Ok(self.do_cursor_movement(dir, reach, |b| { b.cursor_jump(dir); })),
+            Event::CursorMove(dir, reach) => return Ok(self.for_each_cursor(|b| b.do_cursor_movement(dir, reach, |b| { b.cursor_move(dir, 1); }))),
+            Event::CursorJump(dir, reach) => return Ok(self.for_each_cursor(|b| b.do_cursor_movement(dir, reach, |b| { b.cursor_jump(dir); }))),
+            Event::JumpToMatchingBracket => return Ok(self.for_each_cursor(|b| {
+                b.jump_to_matching_bracket();
+                b.cursor_mut().reset_base();
+            })),
+            Event::JumpOutOfBlock(dir) => return Ok(self.for_each_cursor(|b| {
+                b.jump_out_of_block(dir);
+                b.cursor_mut().reset_base();
+            })),
             Event::SelectAll => {
+                self.clear_extra_cursors();
                 self.cursor_mut().base = 0;
                 self.cursor_mut().pos = self.len();
                 return Ok(())
             },
+            Event::AddCursor(dir) => return Ok(self.add_cursor_vertical(dir)),
+            Event::AddNextOccurrence => return Ok(self.add_next_occurrence()),
+            Event::SplitSelection => return Ok(self.split_selection()),
+            Event::BlockSelect(dir) => return Ok(self.block_select(dir)),
             _ => {},
         }
 
         self.buffer.pre_edit();
 
+        // Edits that work on whole lines or on the clipboard consider all cursors together
+        match event {
+            Event::Insert('\t') if self.cursor_ids().into_iter().any(|id| self.spans_lines(id)) => {
+                for l in self.selected_lines() {
+                    if let Some(pos) = self.line_start(l) {
+                        self.indent_at(pos);
+                    }
+                }
+            },
+            Event::Comment => { self.toggle_comment(); },
+            Event::Dedent => self.dedent(),
+            Event::MoveLines(dir) => self.move_lines(dir),
+            Event::JoinLines => self.join_lines(),
+            Event::SortLines(order) => self.transform_lines(|lines| sort_lines(lines, order)),
+            Event::ReverseLines => self.transform_lines(|mut lines| {
+                lines.reverse();
+                lines
+            }),
+            Event::DedupeLines => self.transform_lines(|lines| {
+                let mut seen = HashSet::new();
+                lines
+                    .into_iter()
+                    .filter(|line| seen.insert(line.clone()))
+                    .collect()
+            }),
+            Event::Reindent => self.reindent(),
+            Event::Cut => {
+                #[cfg(feature = "clipboard")]
+                {
+                    let text = self.selections().join("\n");
+                    let _ = ClipboardContext::new()
+                        .and_then(|mut ctx| ctx.set_contents(text));
+                }
+                self.for_each_cursor(|b| b.remove_selection());
+            },
+            Event::Copy => {
+                #[cfg(feature = "clipboard")]
+                {
+                    let text = self.selections().join("\n");
+                    let _ = ClipboardContext::new()
+                        .and_then(|mut ctx| ctx.set_contents(text));
+                }
+            },
+            #[cfg(feature = "clipboard")]
+            Event::Paste => match ClipboardContext::new().and_then(|mut ctx| ctx.get_contents()) {
+                Ok(s) => {
+                    // Text copied from as many cursors as there are now is split between them again
+                    let lines = s.split('\n').collect::<Vec<_>>();
+                    let split = lines.len() > 1 && lines.len() == self.cursor_ids().len();
+                    let mut i = 0;
+                    self.for_each_cursor(|b| {
+                        b.remove_selection();
+                        b.insert_str(if split { lines[i] } else { &s });
+                        i += 1;
+                    });
+                },
+                Err(_) => {},
+            },
+            event => {
+                let mut result = Ok(());
+                self.for_each_cursor(|b| if let Err(event) = b.handle_edit(event.clone()) {
+                    result = Err(event);
+                });
+                return result;
+            },
+        }
+        Ok(())
+    }
+
+    // Apply an edit at the current cursor
+    fn handle_edit(&mut self, event: Event) -> Result<(), Event> {
         match event {
             // Mutate
 			Event::Insert(c) => {
@@ -635,21 +1715,7 @@ impl<'a> BufferGuard<'a> {
                             }
                         }
 			        },
-                    '\t' => {
-                        let base_line = self.pos_loc(self.cursor().base).y;
-                        let pos_line = self.pos_loc(self.cursor().pos).y;
-
-                        if base_line != pos_line {
-                            (base_line.min(pos_line)..=base_line.max(pos_line))
-                                .for_each(|l| {
-                                    if let Some(pos) = self.line_start(l) {
-                                        self.indent_at(pos);
-                                    }
-                                });
-                        } else {
-                            self.indent_at(self.cursor().pos);
-                        }
-                    },
+                    '\t' => self.indent_at(self.cursor().pos),
                     c => {
                         self.remove_selection();
                         self.insert(c);
@@ -691,46 +1757,271 @@ impl<'a> BufferGuard<'a> {
             },
             Event::Backspace => self.backspace(),
             Event::BackspaceWord => self.backspace_word(),
+            Event::Delete if self.cursor().is_reaching() => self.remove_selection(),
             Event::Delete => self.delete(),
             Event::Duplicate if self.cursor().is_reaching() => self.insert_str(&self.selection().collect::<String>()),
             Event::Duplicate => self.duplicate_line(),
-            Event::Comment => {
-                let base_line = self.pos_loc(self.cursor().base).y;
-                let pos_line = self.pos_loc(self.cursor().pos).y;
-
-                for l in base_line.min(pos_line)..=base_line.max(pos_line) {
-                    if let Some(pos) = self.line_start(l) {
-                        self.insert_at(pos + 0, '/');
-                        self.insert_at(pos + 1, '/');
-                        self.insert_at(pos + 2, ' ');
-                    }
-                }
-            },
-            Event::Cut => {
-                #[cfg(feature = "clipboard")]
-                {
-                    let _ = ClipboardContext::new()
-                        .and_then(|mut ctx| ctx.set_contents(self.selection().collect()));
-                }
-                self.remove_selection();
-            },
-            Event::Copy => {
-                #[cfg(feature = "clipboard")]
-                {
-                    let _ = ClipboardContext::new()
-                        .and_then(|mut ctx| ctx.set_contents(self.selection().collect()));
-                }
-            },
-            #[cfg(feature = "clipboard")]
-            Event::Paste => match ClipboardContext::new().and_then(|mut ctx| ctx.get_contents()) {
-                Ok(s) => {
-                    self.remove_selection();
-                    self.insert_str(&s)
-                },
-                Err(_) => {},
-            },
             _ => return Err(event),
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Guard the buffer's only cursor, after typing the text into it
+    fn guard_with<'a>(buf: &'a mut SharedBuffer, text: &str) -> BufferGuard<'a> {
+        let cursor_id = buf.insert_cursor(Cursor::default());
+        let mut guard = BufferGuard { buffer: buf, cursor_id };
+        guard.insert_str(text);
+        guard
+    }
+
+    #[test]
+    fn multiple_cursors() {
+        let mut buf = SharedBuffer::default();
+        let mut guard = guard_with(&mut buf, "ab\nab\nef");
+        *guard.cursor_mut() = Cursor { base: 1, pos: 1, preferred_x: None };
+        guard.handle(Event::AddCursor(Dir::Down)).unwrap();
+        guard.handle(Event::AddCursor(Dir::Down)).unwrap();
+        guard.handle(Event::Insert('x')).unwrap();
+        assert_eq!(guard.content().to_string(), "axb\naxb\nexf\n");
+
+        guard.handle(Event::Backspace).unwrap();
+        assert_eq!(guard.content().to_string(), "ab\nab\nef\n");
+
+        guard.clear_extra_cursors();
+        *guard.cursor_mut() = Cursor::default();
+        guard.handle(Event::AddNextOccurrence).unwrap();
+        guard.handle(Event::AddNextOccurrence).unwrap();
+        assert_eq!(guard.cursors().len(), 2);
+    }
+
+    #[test]
+    fn block_selection() {
+        let mut buf = SharedBuffer::default();
+        let mut guard = guard_with(&mut buf, "abcd\n\tef\ngh");
+        *guard.cursor_mut() = Cursor { base: 1, pos: 1, preferred_x: None };
+        for dir in [Dir::Right, Dir::Right, Dir::Down, Dir::Down] {
+            guard.handle(Event::BlockSelect(dir)).unwrap();
+        }
+        assert_eq!(guard.block(), Some((Vec2::new(1, 0), Vec2::new(3, 2))));
+
+        // Columns inside a tab select the whole tab
+        guard.handle(Event::Insert('x')).unwrap();
+        assert_eq!(guard.content().to_string(), "axd\nxef\ngx\n");
+    }
+
+    #[test]
+    fn go_to_loc() {
+        let mut buf = SharedBuffer::default();
+        let mut guard = guard_with(&mut buf, "ab\n\tcd\nef");
+        // The column counts the tab as one character
+        guard.go_to_loc(Vec2::new(2, 1));
+        assert_eq!(guard.cursor().pos, 5);
+        assert_eq!(guard.cursor().base, 5);
+
+        // Columns past the end of the line stop before its newline
+        guard.go_to_loc(Vec2::new(10, 1));
+        assert_eq!(guard.cursor().pos, 6);
+        guard.go_to_loc(Vec2::new(1, 10));
+        assert_eq!(guard.cursor().pos, 8);
+    }
+
+    #[test]
+    fn replace_ranges() {
+        let mut buf = SharedBuffer::default();
+        let mut guard = guard_with(&mut buf, "foo bar foo");
+        guard.replace_ranges(&[(0..3, "x".to_string()), (8..11, "yz".to_string())]);
+        assert_eq!(guard.content().to_string(), "x bar yz\n");
+
+        // Typing straight afterwards is a separate step, and all replacements are undone together
+        guard.insert('!');
+        guard.undo();
+        assert_eq!(guard.content().to_string(), "x bar yz\n");
+        guard.undo();
+        assert_eq!(guard.content().to_string(), "foo bar foo\n");
+    }
+
+    #[test]
+    fn brackets() {
+        let mut buf = SharedBuffer::default();
+        buf.set_lang(Some("rs".to_string()));
+        let mut guard = guard_with(&mut buf, "f(a, \")\", [b]) { g(); }");
+        // Brackets within strings are ignored
+        assert_eq!(guard.bracket_partner(1), Some(13));
+        assert_eq!(guard.bracket_partner(13), Some(1));
+        assert_eq!(guard.bracket_partner(6), None);
+
+        guard.cursor_mut().pos = 14;
+        assert_eq!(guard.matching_bracket(), Some((13, 1)));
+        guard.handle(Event::JumpToMatchingBracket).unwrap();
+        assert_eq!(guard.cursor().pos, 2);
+
+        guard.cursor_mut().pos = 11;
+        guard.handle(Event::JumpOutOfBlock(Dir::Up)).unwrap();
+        assert_eq!(guard.cursor().pos, 10);
+        guard.handle(Event::JumpOutOfBlock(Dir::Up)).unwrap();
+        assert_eq!(guard.cursor().pos, 1);
+
+        guard.cursor_mut().pos = 11;
+        guard.handle(Event::JumpOutOfBlock(Dir::Down)).unwrap();
+        assert_eq!(guard.cursor().pos, 13);
+        guard.handle(Event::JumpOutOfBlock(Dir::Down)).unwrap();
+        assert_eq!(guard.cursor().pos, 14);
+
+        guard.cursor_jump(Dir::Down);
+        assert_eq!(guard.cursor().pos, 23);
+        guard.cursor_jump(Dir::Up);
+        assert_eq!(guard.cursor().pos, 15);
+    }
+
+    #[test]
+    fn comments() {
+        let mut buf = SharedBuffer::default();
+        buf.set_lang(Some("rs".to_string()));
+        let mut guard = guard_with(&mut buf, "\tfoo\n\n\t\tbar");
+        *guard.cursor_mut() = Cursor { base: 0, pos: guard.len(), preferred_x: None };
+        guard.handle(Event::Comment).unwrap();
+        assert_eq!(guard.content().to_string(), "\t// foo\n\n\t// \tbar\n");
+        guard.handle(Event::Comment).unwrap();
+        assert_eq!(guard.content().to_string(), "\tfoo\n\n\t\tbar\n");
+
+        // Lines are only uncommented if all of them are commented
+        guard.undo();
+        guard.replace_ranges(&[(1..4, String::new())]);
+        guard.handle(Event::Comment).unwrap();
+        assert_eq!(guard.content().to_string(), "\t// foo\n\n\t// // \tbar\n");
+
+        guard.buffer.set_lang(Some("md".to_string()));
+        guard.replace_ranges(&[(0..guard.len(), "a\n<!-- b -->".to_string())]);
+        *guard.cursor_mut() = Cursor { base: 0, pos: guard.len(), preferred_x: None };
+        guard.handle(Event::Comment).unwrap();
+        assert_eq!(guard.content().to_string(), "<!-- a -->\n<!-- <!-- b --> -->\n");
+        guard.replace_ranges(&[(0..11, String::new())]);
+        guard.handle(Event::Comment).unwrap();
+        assert_eq!(guard.content().to_string(), "<!-- b -->\n");
+
+        // Unknown languages are left alone rather than guessed at
+        guard.buffer.set_lang(None);
+        assert!(!guard.toggle_comment());
+        assert_eq!(guard.content().to_string(), "<!-- b -->\n");
+    }
+
+    #[test]
+    fn indentation() {
+        let mut buf = SharedBuffer::default();
+        buf.set_lang(Some("rs".to_string()));
+        let mut guard = guard_with(&mut buf, "fn f() {\n      a(\"{\");\n\tif b {\nc\n\n  }\n}");
+        *guard.cursor_mut() = Cursor { base: 0, pos: guard.len(), preferred_x: None };
+        guard.handle(Event::Dedent).unwrap();
+        assert_eq!(guard.content().to_string(), "fn f() {\n    a(\"{\");\nif b {\nc\n\n}\n}\n");
+
+        // Brackets within strings don't count
+        guard.handle(Event::Reindent).unwrap();
+        assert_eq!(guard.content().to_string(), "fn f() {\n    a(\"{\");\n    if b {\n        c\n\n    }\n}\n");
+
+        // Both are single undo steps
+        guard.undo();
+        guard.undo();
+        assert_eq!(guard.content().to_string(), "fn f() {\n      a(\"{\");\n\tif b {\nc\n\n  }\n}\n");
+    }
+
+    #[test]
+    fn line_commands() {
+        let mut buf = SharedBuffer::default();
+        let mut guard = guard_with(&mut buf, "b\n10\nB\n9\nb\na");
+        *guard.cursor_mut() = Cursor { base: 0, pos: guard.len(), preferred_x: None };
+        guard.handle(Event::SortLines(SortOrder::CaseSensitive)).unwrap();
+        assert_eq!(guard.content().to_string(), "10\n9\nB\na\nb\nb\n");
+        guard.handle(Event::SortLines(SortOrder::CaseInsensitive)).unwrap();
+        assert_eq!(guard.content().to_string(), "10\n9\na\nB\nb\nb\n");
+        guard.handle(Event::SortLines(SortOrder::Numeric)).unwrap();
+        assert_eq!(guard.content().to_string(), "a\nB\nb\nb\n9\n10\n");
+        guard.handle(Event::ReverseLines).unwrap();
+        assert_eq!(guard.content().to_string(), "10\n9\nb\nb\nB\na\n");
+        guard.handle(Event::DedupeLines).unwrap();
+        assert_eq!(guard.content().to_string(), "10\n9\nb\nB\na\n");
+        // The result stays selected, and each command is a single undo step
+        assert_eq!((guard.cursor().base, guard.cursor().pos), (0, guard.len()));
+        guard.undo();
+        assert_eq!(guard.content().to_string(), "10\n9\nb\nb\nB\na\n");
+
+        // Moving lines carries the selection with them
+        *guard.cursor_mut() = Cursor { base: 3, pos: 6, preferred_x: None };
+        guard.handle(Event::MoveLines(Dir::Up)).unwrap();
+        assert_eq!(guard.content().to_string(), "9\nb\n10\nb\nB\na\n");
+        assert_eq!((guard.cursor().base, guard.cursor().pos), (0, 3));
+        guard.handle(Event::MoveLines(Dir::Up)).unwrap();
+        assert_eq!(guard.content().to_string(), "9\nb\n10\nb\nB\na\n");
+        guard.handle(Event::MoveLines(Dir::Down)).unwrap();
+        assert_eq!(guard.content().to_string(), "10\n9\nb\nb\nB\na\n");
+        assert_eq!((guard.cursor().base, guard.cursor().pos), (3, 6));
+
+        // A selection ending at the start of a line leaves that line out
+        *guard.cursor_mut() = Cursor { base: 0, pos: 5, preferred_x: None };
+        guard.handle(Event::MoveLines(Dir::Down)).unwrap();
+        assert_eq!(guard.content().to_string(), "b\n10\n9\nb\nB\na\n");
+        assert_eq!((guard.cursor().base, guard.cursor().pos), (2, 7));
+
+        guard.replace_ranges(&[(0..guard.len(), "f(\n    a,\n    b\n)\nx".to_string())]);
+        *guard.cursor_mut() = Cursor { base: 0, pos: 17, preferred_x: None };
+        guard.handle(Event::JoinLines).unwrap();
+        assert_eq!(guard.content().to_string(), "f(a, b)\nx\n");
+        assert_eq!(guard.cursor().pos, 6);
+        guard.handle(Event::JoinLines).unwrap();
+        assert_eq!(guard.content().to_string(), "f(a, b) x\n");
+    }
+
+    #[test]
+    fn redo_after_non_edit() {
+        let mut buf = SharedBuffer::default();
+        let mut guard = guard_with(&mut buf, "");
+
+        guard.handle(Event::Insert('a')).unwrap();
+        guard.buffer.replace_ranges(&[(1..1, "b".to_string())]);
+        let nodes = guard.buffer.history().walk().len();
+        guard.undo();
+        assert_eq!(guard.content().to_string(), "a\n");
+
+        // Events that edit nothing don't start a change, so redo still has somewhere to go
+        guard.handle(Event::Copy).unwrap();
+        guard.handle(Event::CursorMove(Dir::Left, false)).unwrap();
+        guard.redo();
+        assert_eq!(guard.content().to_string(), "ab\n");
+        assert_eq!(guard.buffer.history().walk().len(), nodes);
+    }
+
+    #[test]
+    fn save_as_same_file() {
+        let dir = PathBuf::from("target").join(format!("zte-test-save-as-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.txt");
+        fs::write(&path, "a\n").unwrap();
+
+        // Saving a file under another name for itself must not remove it afterwards
+        let mut buf = SharedBuffer::open_or_create(path.clone()).unwrap();
+        buf.replace_ranges(&[(1..1, "b".to_string())]);
+        buf.save_as(PathBuf::from(".").join(&path)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "ab\n");
+
+        drop(buf);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn open_errors() {
+        let dir = PathBuf::from("target").join(format!("zte-test-open-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        // Missing files are created on save, but files that can't be read are reported
+        assert!(SharedBuffer::open_or_create(dir.join("missing.txt")).unwrap().is_unsaved());
+        assert!(SharedBuffer::open_or_create(dir.clone()).is_err());
+        fs::write(dir.join("file.txt"), "a\n").unwrap();
+        assert!(SharedBuffer::open_or_create(dir.join("file.txt").join("b.txt")).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    path::{Path, PathBuf},
+    collections::HashMap,
+    sync::{Mutex, atomic::{AtomicUsize, Ordering}},
+    process::{self, Stdio},
+    time::{SystemTime, UNIX_EPOCH},
+    fs,
+    io,
+};
+use serde_derive::{Serialize, Deserialize};
+use lazy_static::lazy_static;
+use crate::config;
+use super::{
+    Content,
+    history::path_hash,
+    file::resolve,
+};
+
+// Unsaved content waiting to be written to a swap file
+struct Entry {
+    content: Content,
+    dirty: bool,
+}
+
+lazy_static! {
+    // Kept globally so that the panic hook can flush it without access to the editor state
+    static ref JOURNAL: Mutex<HashMap<PathBuf, Entry>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Serialize, Deserialize)]
+struct SwapFile {
+    file: PathBuf,
+    // Milliseconds since the epoch
+    time: u64,
+    text: String,
+}
+
+// Content recovered from a swap file
+pub struct Recovery {
+    // The file the content belongs to, or the key of the untitled buffer that it was journalled under
+    pub file: PathBuf,
+    pub content: Content,
+    pub time: SystemTime,
+}
+
+// Untitled buffers are journalled under keys unique to the buffer and the process, which can't be mistaken for the
+// absolute paths of files
+const UNTITLED_PREFIX: &str = "untitled-";
+
+// Make a key to journal an untitled buffer under
+pub fn untitled_key() -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    PathBuf::from(format!("{}{}-{}", UNTITLED_PREFIX, process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)))
+}
+
+fn swap_path(file: &Path) -> Option<PathBuf> {
+    config::data_path("swap", format!("{:016x}.json", path_hash(file)))
+}
+
+// Whether a process that journals untitled buffers is still running, in which case its swap files aren't leftovers
+fn is_running(pid: u32) -> bool {
+    let status = process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(Stdio::null())
+        .status();
+    matches!(status, Ok(status) if status.success())
+}
+
+// The process that an untitled buffer was journalled by
+fn untitled_pid(key: &Path) -> Option<u32> {
+    key.to_str()?
+        .strip_prefix(UNTITLED_PREFIX)?
+        .split('-')
+        .next()?
+        .parse()
+        .ok()
+}
+
+// Record the latest unsaved content of a file. It is written to disk on the next flush. Files are journalled under
+// their resolved path, like their history, so that they are found again however they are next opened.
+pub fn record(file: &Path, content: &Content) {
+    if let Ok(mut journal) = JOURNAL.lock() {
+        journal.insert(resolve(file), Entry { content: content.clone(), dirty: true });
+    }
+}
+
+// Forget about a file's unsaved content, removing its swap file
+pub fn discard(file: &Path) {
+    if let Ok(mut journal) = JOURNAL.lock() {
+        journal.remove(&resolve(file));
+    }
+    if let Some(path) = swap_path(file) {
+        let _ = fs::remove_file(path);
+    }
+}
+
+// Write all recorded content that has changed since the last flush to swap files
+pub fn flush() {
+    // This is called from the panic hook, so avoid deadlocking if the panic happened while the journal was locked
+    let mut journal = match JOURNAL.try_lock() {
+        Ok(journal) => journal,
+        Err(_) => return,
+    };
+
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64);
+    for (file, entry) in journal.iter_mut().filter(|(_, e)| e.dirty) {
+        let swap = SwapFile {
+            file: file.clone(),
+            time,
+            text: entry.content.to_string(),
+        };
+        let result = swap_path(file)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no data directory"))
+            .and_then(|path| fs::write(path, serde_json::to_vec(&swap)?));
+        match result {
+            Ok(()) => entry.dirty = false,
+            Err(err) => log::warn!("Failed to write swap file for {:?}: {:?}", file, err),
+        }
+    }
+}
+
+fn read_swap(path: &Path) -> Option<Recovery> {
+    let swap = serde_json::from_slice::<SwapFile>(&fs::read(path).ok()?).ok()?;
+    Some(Recovery {
+        file: swap.file,
+        content: Content::from(swap.text),
+        time: UNIX_EPOCH + std::time::Duration::from_millis(swap.time),
+    })
+}
+
+// Find unsaved content for the given file left behind by an earlier session
+pub fn find(file: &Path) -> Option<Recovery> {
+    read_swap(&swap_path(file)?).filter(|recovery| recovery.file == resolve(file))
+}
+
+// Find the unsaved content of untitled buffers left behind by sessions that have ended, oldest first. Those of
+// sessions still running (this one included) are theirs to keep.
+pub fn find_untitled() -> Vec<Recovery> {
+    let mut recoveries = config::data_path("swap", "")
+        .and_then(|dir| fs::read_dir(dir).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| read_swap(&entry.ok()?.path()))
+        .filter(|recovery| matches!(untitled_pid(&recovery.file), Some(pid) if !is_running(pid)))
+        .collect::<Vec<_>>();
+    recoveries.sort_by_key(|recovery| recovery.time);
+    recoveries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn untitled_owners() {
+        // This session's untitled buffers belong to a running process, so aren't offered for recovery
+        let key = untitled_key();
+        assert_eq!(untitled_pid(&key), Some(process::id()));
+        assert!(is_running(process::id()));
+        assert_eq!(untitled_pid(Path::new("/home/untitled-1-0")), None);
+    }
+}
@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use directories::ProjectDirs;
 use serde_derive::{Serialize, Deserialize};
 use lazy_static::lazy_static;
@@ -15,17 +15,39 @@ lazy_static! {
         path.push(CONFIG_FILENAME);
         path
     };
+
+    /// The global configuration, loaded on first use
+    pub static ref CONFIG: Config = Config::load().unwrap_or_else(|err| {
+        log::warn!("Failed to load config: {:?}", err);
+        Config::default()
+    });
+
+    static ref DATA_DIR: Option<PathBuf> = ProjectDirs::from("com", "jsbarretto", "zte")
+        .map(|dirs| dirs.data_dir().to_owned());
+}
+
+/// Find the path of a file within the given subdirectory of the data directory, creating the subdirectory if it
+/// does not already exist
+pub fn data_path(subdir: impl AsRef<Path>, name: impl AsRef<Path>) -> Option<PathBuf> {
+    let mut path = DATA_DIR.as_ref()?.join(subdir);
+    std::fs::create_dir_all(&path).ok()?;
+    path.push(name);
+    Some(path)
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Config {
     //theme: Theme,
+    /// Keep a `~` backup of the previous version of a file when saving over it
+    #[serde(default)]
+    pub backup: bool,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             //theme: Theme::default()
+            backup: false,
         }
     }
 }
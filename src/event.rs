@@ -27,6 +27,21 @@ impl Dir {
     }
 }
 
+#[derive(Copy, Clone, Debug)]
+pub enum SearchOption {
+    CaseSensitive,
+    WholeWord,
+    Regex,
+    InSelection,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum SortOrder {
+    CaseSensitive,
+    CaseInsensitive,
+    Numeric,
+}
+
 #[derive(Clone, Debug)]
 pub enum Event {
     Insert(char),
@@ -34,31 +49,61 @@ pub enum Event {
     BackspaceWord,
     Delete,
     Comment,
+    Dedent,
+    Reindent,
     Undo,
     Redo,
+    SwitchUndoBranch(Dir),
     CursorMove(Dir, bool),
     CursorJump(Dir, bool),
+    JumpToMatchingBracket,
+    JumpOutOfBlock(Dir),
     PageMove(Dir, bool),
     SelectAll,
+    AddCursor(Dir),
+    AddNextOccurrence,
+    SplitSelection,
+    BlockSelect(Dir),
+    ClearCursors,
     SwitchEditor(Dir),
     NewEditor(Dir),
     NewTerminal(Dir),
+    OpenProjectSearch,
+    OpenProjectReplace,
     CloseEditor,
     OpenPrompt,
     OpenSwitcher,
     OpenOpener,
+    OpenSaveAs,
+    OpenUndoTree,
+    OpenSearch,
+    OpenReplace,
+    OpenGoTo,
+    GoTo(Vec2<usize>),
+    ReplaceAll,
+    ToggleSearchOption(SearchOption),
     NewFile(PathBuf),
     OpenFile(PathBuf),
+    OpenFileAt(PathBuf, Vec2<usize>),
     CloseMenu,
     NewBuffer,
     SwitchBuffer(BufferHandle),
     CloseBuffer { force: bool },
     ConfirmCloseBuffer(BufferHandle),
     SaveBuffer,
+    SaveAs { path: PathBuf, force: bool },
+    ConfirmOverwrite(PathBuf),
+    ToggleLineEnding,
+    CycleIndent,
     Cut,
     Copy,
     Paste,
     Duplicate,
+    MoveLines(Dir),
+    JoinLines,
+    SortLines(SortOrder),
+    ReverseLines,
+    DedupeLines,
     Escape,
     Quit,
     Tick,
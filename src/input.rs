@@ -1,7 +1,7 @@
 use std::{
     thread,
     sync::mpsc::{channel, Receiver},
-    io::stdin,
+    io::{stdin, Read},
 };
 /*
 use crossterm::{
@@ -17,6 +17,8 @@ use termion::{
 use crate::{
     Event,
     Dir,
+    SearchOption,
+    SortOrder,
 };
 
 pub fn begin_reading() -> Receiver<Event> {
@@ -30,7 +32,14 @@ pub fn begin_reading() -> Receiver<Event> {
         }
     });
 
-    thread::spawn(move || for event in stdin().events() {
+    // If stdin was used to pipe in content, keys must be read from the terminal itself
+    let input: Box<dyn Read + Send> = if termion::is_tty(&stdin()) {
+        Box::new(stdin())
+    } else {
+        Box::new(termion::get_tty().expect("Failed to open the terminal for input"))
+    };
+
+    thread::spawn(move || for event in input.events() {
         let events = match event.unwrap() {
             // Buffer movement
 
@@ -54,8 +63,23 @@ pub fn begin_reading() -> Receiver<Event> {
             InputEvent::Unsupported(event) if event == &[27, 91, 49, 59, 54, 65] => vec![Event::CursorJump(Dir::Up, true)],
             InputEvent::Unsupported(event) if event == &[27, 91, 49, 59, 54, 66] => vec![Event::CursorJump(Dir::Down, true)],
 
+            InputEvent::Key(KeyEvent::Alt('j')) => vec![Event::JumpToMatchingBracket],
+            InputEvent::Key(KeyEvent::Alt('{')) => vec![Event::JumpOutOfBlock(Dir::Up)],
+            InputEvent::Key(KeyEvent::Alt('}')) => vec![Event::JumpOutOfBlock(Dir::Down)],
+
             InputEvent::Key(KeyEvent::Ctrl('a')) => vec![Event::SelectAll],
 
+            // Multiple cursors
+
+            InputEvent::Unsupported(event) if event == &[27, 91, 49, 59, 55, 65] => vec![Event::AddCursor(Dir::Up)],
+            InputEvent::Unsupported(event) if event == &[27, 91, 49, 59, 55, 66] => vec![Event::AddCursor(Dir::Down)],
+            InputEvent::Key(KeyEvent::Alt('n')) => vec![Event::AddNextOccurrence],
+            InputEvent::Key(KeyEvent::Alt('m')) => vec![Event::SplitSelection],
+            InputEvent::Unsupported(event) if event == &[27, 91, 49, 59, 52, 68] => vec![Event::BlockSelect(Dir::Left)],
+            InputEvent::Unsupported(event) if event == &[27, 91, 49, 59, 52, 67] => vec![Event::BlockSelect(Dir::Right)],
+            InputEvent::Unsupported(event) if event == &[27, 91, 49, 59, 52, 65] => vec![Event::BlockSelect(Dir::Up)],
+            InputEvent::Unsupported(event) if event == &[27, 91, 49, 59, 52, 66] => vec![Event::BlockSelect(Dir::Down)],
+
             // Buffer editing
 
             InputEvent::Key(KeyEvent::Char(c)) => vec![Event::Insert(c)],
@@ -64,27 +88,59 @@ pub fn begin_reading() -> Receiver<Event> {
             InputEvent::Key(KeyEvent::Delete) => vec![Event::Delete],
             InputEvent::Key(KeyEvent::Ctrl('z')) => vec![Event::Undo],
             InputEvent::Key(KeyEvent::Ctrl('y')) => vec![Event::Redo],
+            InputEvent::Key(KeyEvent::Alt('z')) => vec![Event::SwitchUndoBranch(Dir::Left)],
+            InputEvent::Key(KeyEvent::Alt('y')) => vec![Event::SwitchUndoBranch(Dir::Right)],
             InputEvent::Key(KeyEvent::Esc) => vec![Event::Escape],
             InputEvent::Key(KeyEvent::PageUp) => vec![Event::PageMove(Dir::Up, false)],
             InputEvent::Key(KeyEvent::PageDown) => vec![Event::PageMove(Dir::Down, false)],
             InputEvent::Key(KeyEvent::Ctrl(';')) => vec![Event::Comment],
+            InputEvent::Key(KeyEvent::BackTab) => vec![Event::Dedent],
+            InputEvent::Key(KeyEvent::Alt('=')) => vec![Event::Reindent],
 
             // Buffer manipulation
 
             InputEvent::Key(KeyEvent::Ctrl('b')) => vec![Event::OpenSwitcher],
             InputEvent::Key(KeyEvent::Ctrl('o')) => vec![Event::OpenOpener],
+            InputEvent::Key(KeyEvent::Ctrl('u')) => vec![Event::OpenUndoTree],
+
+            // Searching
+
+            InputEvent::Key(KeyEvent::Ctrl('f')) => vec![Event::OpenSearch],
+            InputEvent::Key(KeyEvent::Alt('c')) => vec![Event::ToggleSearchOption(SearchOption::CaseSensitive)],
+            InputEvent::Key(KeyEvent::Alt('b')) => vec![Event::ToggleSearchOption(SearchOption::WholeWord)],
+            InputEvent::Key(KeyEvent::Alt('r')) => vec![Event::ToggleSearchOption(SearchOption::Regex)],
+            InputEvent::Key(KeyEvent::Alt('i')) => vec![Event::ToggleSearchOption(SearchOption::InSelection)],
+            InputEvent::Key(KeyEvent::Ctrl('r')) => vec![Event::OpenReplace],
+            InputEvent::Key(KeyEvent::Alt('e')) => vec![Event::ReplaceAll],
+            InputEvent::Key(KeyEvent::Ctrl('g')) => vec![Event::OpenProjectSearch],
+            InputEvent::Key(KeyEvent::Ctrl('l')) => vec![Event::OpenGoTo],
+            InputEvent::Key(KeyEvent::Alt('g')) => vec![Event::OpenProjectReplace],
             InputEvent::Key(KeyEvent::Ctrl('q')) => vec![Event::CloseBuffer { force: false }],
 
             // Buffer actions
 
             InputEvent::Key(KeyEvent::Ctrl('n')) => vec![Event::NewBuffer],
             InputEvent::Key(KeyEvent::Ctrl('s')) => vec![Event::SaveBuffer],
+            InputEvent::Key(KeyEvent::Ctrl('w')) => vec![Event::OpenSaveAs],
+            InputEvent::Key(KeyEvent::Alt('l')) => vec![Event::ToggleLineEnding],
+            InputEvent::Key(KeyEvent::Alt('I')) => vec![Event::CycleIndent],
 
             InputEvent::Key(KeyEvent::Ctrl('x')) => vec![Event::Cut],
             InputEvent::Key(KeyEvent::Ctrl('c')) => vec![Event::Copy],
             InputEvent::Key(KeyEvent::Ctrl('v')) => vec![Event::Paste],
             InputEvent::Key(KeyEvent::Ctrl('d')) => vec![Event::Duplicate],
 
+            // Line manipulation
+
+            InputEvent::Key(KeyEvent::Alt('K')) => vec![Event::MoveLines(Dir::Up)],
+            InputEvent::Key(KeyEvent::Alt('J')) => vec![Event::MoveLines(Dir::Down)],
+            InputEvent::Key(KeyEvent::Alt('o')) => vec![Event::JoinLines],
+            InputEvent::Key(KeyEvent::Alt('t')) => vec![Event::SortLines(SortOrder::CaseSensitive)],
+            InputEvent::Key(KeyEvent::Alt('T')) => vec![Event::SortLines(SortOrder::CaseInsensitive)],
+            InputEvent::Key(KeyEvent::Alt('#')) => vec![Event::SortLines(SortOrder::Numeric)],
+            InputEvent::Key(KeyEvent::Alt('v')) => vec![Event::ReverseLines],
+            InputEvent::Key(KeyEvent::Alt('u')) => vec![Event::DedupeLines],
+
             // Tile movement
 
             InputEvent::Key(KeyEvent::Alt('a')) => vec![Event::SwitchEditor(Dir::Left)],
@@ -7,16 +7,22 @@ mod ui;
 mod buffer;
 mod state;
 
-use std::{panic, env};
+use std::{
+    panic,
+    env,
+    path::{Path, PathBuf},
+    io::{self, Read},
+};
+use vek::*;
 use backtrace::Backtrace;
 use clap::{App, Arg};
 use crate::{
     config::Config,
     display::{Display, Color},
-    event::{Dir, Event},
+    event::{Dir, Event, SearchOption, SortOrder},
     draw::Canvas,
-    ui::{MainUi, Theme},
-    buffer::{BufferId, BufferHandle, Line, Cursor, CursorId},
+    ui::{MainUi, Theme, Split},
+    buffer::{BufferId, BufferHandle, Line, Cursor, CursorId, shared::SharedBuffer},
     state::State,
 };
 
@@ -25,6 +31,8 @@ const LOG_FILENAME: &str = concat!(env!("CARGO_PKG_NAME"), ".log");
 fn setup() -> Config {
     // Set up panic hook
     panic::set_hook(Box::new(move |info| {
+        // Save unsaved work before anything else can go wrong
+        buffer::swap::flush();
         log::error!("{}", info);
         log::error!("{:?}", Backtrace::new());
         eprintln!("Panic: {}", info);
@@ -44,10 +52,50 @@ fn setup() -> Config {
     }
 
     // Load config
-    Config::load().unwrap_or_else(|err| {
-        log::warn!("Failed to load config: {:?}", err);
-        Config::default()
-    })
+    config::CONFIG.clone()
+}
+
+// Split a trailing `:line` or `:line:col` from a path, as printed by compilers and grep. Lines and columns are
+// numbered from 1, but the location is returned numbered from 0.
+fn parse_location(arg: &str) -> (PathBuf, Option<Vec2<usize>>) {
+    // A file that really has this name takes priority
+    if Path::new(arg).exists() {
+        return (arg.into(), None);
+    }
+
+    let mut rest = arg.strip_suffix(':').unwrap_or(arg);
+    let mut nums = Vec::new();
+    while nums.len() < 2 {
+        match rest.rfind(':').and_then(|i| Some((i, rest[i + 1..].parse::<usize>().ok()?))) {
+            Some((i, n)) if i > 0 => {
+                nums.insert(0, n.saturating_sub(1));
+                rest = &rest[..i];
+            },
+            _ => break,
+        }
+    }
+
+    match nums[..] {
+        [line] => (rest.into(), Some(Vec2::new(0, line))),
+        [line, col] => (rest.into(), Some(Vec2::new(col, line))),
+        _ => (arg.into(), None),
+    }
+}
+
+// Find the files to open, accepting both `path:line[:col]` and `+line path`
+fn parse_paths<'a>(args: impl Iterator<Item=&'a str>) -> Vec<(PathBuf, Option<Vec2<usize>>)> {
+    let mut paths = Vec::new();
+    let mut next_line = None;
+    for arg in args {
+        match arg.strip_prefix('+').and_then(|n| n.parse::<usize>().ok()) {
+            Some(line) => next_line = Some(line.saturating_sub(1)),
+            None => {
+                let (path, loc) = parse_location(arg);
+                paths.push((path, loc.or_else(|| Some(Vec2::new(0, next_line.take()?)))));
+            },
+        }
+    }
+    paths
 }
 
 fn main() {
@@ -58,19 +106,59 @@ fn main() {
         .author(env!("CARGO_PKG_AUTHORS"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
         .arg(Arg::with_name("PATH")
-            .help("Specify a file to edit")
+            .help("Specify a file to edit, optionally as `path:line[:col]` or preceded by `+line`. `-` reads from stdin.")
             .multiple(true))
+        .arg(Arg::with_name("split")
+            .long("split")
+            .takes_value(true)
+            .possible_values(&["vertical", "horizontal"])
+            .help("Lay out multiple files side by side (vertical) or one above another (horizontal)"))
+        .arg(Arg::with_name("lang")
+            .long("lang")
+            .takes_value(true)
+            .help("Highlight content read from stdin as the given language (e.g: `rs`, `toml`, `md`, `log`)"))
+        .arg(Arg::with_name("read-only")
+            .long("read-only")
+            .help("Prevent content read from stdin from being edited"))
         .get_matches();
 
 
+    let paths = matches
+        .values_of("PATH")
+        .map(parse_paths)
+        .unwrap_or_default();
+    let (stdin_paths, paths) = paths
+        .into_iter()
+        .partition::<Vec<_>, _>(|(path, _)| path == Path::new("-"));
+
+    // Piped content has to be read before the terminal is taken over
+    let stdin_buf = stdin_paths.first().map(|(_, loc)| {
+        let mut bytes = Vec::new();
+        if let Err(err) = io::stdin().read_to_end(&mut bytes) {
+            log::warn!("Failed to read stdin: {:?}", err);
+        }
+        let mut buf = SharedBuffer::from_bytes(&bytes, matches.is_present("read-only"));
+        buf.set_lang(matches.value_of("lang").map(|lang| lang.to_string()));
+        (buf, *loc)
+    });
+
     let mut display = Display::new();
 
-    let (state, buffers, _) = match matches.values_of("PATH") {
-        Some(paths) => State::from_paths(paths.map(|path| path.to_string().into())),
-        None => (State::default(), Vec::new(), Vec::new()),
+    let (mut state, mut buffers, errors) = State::from_paths(paths.into_iter());
+    if let Some((buf, loc)) = stdin_buf {
+        buffers.insert(0, (state.insert_buffer(buf), loc));
+    }
+
+    let split = match matches.value_of("split") {
+        Some("horizontal") => Split::Horizontal,
+        _ => Split::Vertical,
     };
 
-    let mut ui = MainUi::new(Theme::default(), state, buffers);
+    let mut ui = MainUi::new(Theme::default(), state, buffers, split);
+    // The other files still open, so failing to open one isn't fatal
+    for err in errors {
+        ui.messages().warning(err);
+    }
 
     let event_rx = input::begin_reading();
     loop {
@@ -79,7 +167,10 @@ fn main() {
         display.render();
 
         match event_rx.recv().unwrap() {
-            Event::Tick => display.update_size(),
+            Event::Tick => {
+                display.update_size();
+                ui.tick();
+            },
             event => if ui.handle(event) {
                 break;
             } else {},
@@ -88,3 +179,21 @@ fn main() {
 
     log::info!("Quitting...");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locations() {
+        let paths = parse_paths(["src/nonexistent.rs:42:7", "+10", "a.rs", "b.rs:3:", "c.rs", "d:e", "-"].iter().copied());
+        assert_eq!(paths, vec![
+            (PathBuf::from("src/nonexistent.rs"), Some(Vec2::new(6, 41))),
+            (PathBuf::from("a.rs"), Some(Vec2::new(0, 9))),
+            (PathBuf::from("b.rs"), Some(Vec2::new(0, 2))),
+            (PathBuf::from("c.rs"), None),
+            (PathBuf::from("d:e"), None),
+            (PathBuf::from("-"), None),
+        ]);
+    }
+}
@@ -1,15 +1,27 @@
+mod project;
+
+// Reexports
+pub use self::project::{FileReplacements, Hunk};
+
 use std::{
-    path::PathBuf,
+    path::{Path, PathBuf},
     ops::Deref,
     io,
+    fmt,
     collections::HashMap,
     sync::Arc,
 };
+use vek::*;
 use crate::{
-    buffer::shared::{
-        SharedBuffer,
-        SharedBufferError,
-        BufferGuard,
+    buffer::{
+        shared::{
+            SharedBuffer,
+            SharedBufferError,
+            BufferGuard,
+            DiskChange,
+        },
+        file::resolve,
+        swap::{self, Recovery},
     },
     BufferId,
     BufferHandle,
@@ -20,6 +32,20 @@ use crate::{
 #[derive(Debug)]
 pub enum StateError {
     Shared(SharedBufferError),
+    // A file changed between a project replacement being previewed and applied
+    Changed(PathBuf),
+    // Rewriting a file that isn't open would silently make its mixed line endings uniform
+    MixedEndings(PathBuf),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::Shared(err) => write!(f, "{}", err),
+            StateError::Changed(path) => write!(f, "`{}` changed since the preview", path.display()),
+            StateError::MixedEndings(path) => write!(f, "`{}` has mixed line endings, open it to replace", path.display()),
+        }
+    }
 }
 
 impl From<SharedBufferError> for StateError {
@@ -33,33 +59,54 @@ pub struct State {
     buffers: HashMap<BufferId, (SharedBuffer, Arc<()>)>,
     id_counter: usize,
     recent: Vec<BufferHandle>,
+    // Unsaved content left behind by a previous session that crashed, along with the buffer of its file. The content
+    // of untitled buffers has no buffer to go to until it is recovered.
+    recoveries: Vec<(Option<BufferId>, Recovery)>,
 }
 
 impl State {
-    pub fn from_paths(paths: impl Iterator<Item=PathBuf>) -> (Self, Vec<BufferId>, Vec<StateError>) {
+    // Open each file, along with a location in it for the cursor to start at
+    pub fn from_paths(paths: impl Iterator<Item=(PathBuf, Option<Vec2<usize>>)>) -> (Self, Vec<(BufferId, Option<Vec2<usize>>)>, Vec<StateError>) {
         let mut errors = Vec::new();
 
         let mut this = Self::default();
 
         let mut buffers = Vec::new();
-        for path in paths {
+        for (path, loc) in paths {
             match SharedBuffer::open_or_create(path) {
-                Ok(buf) => buffers.push(this.insert_buffer(buf)),
+                Ok(buf) => {
+                    let id = this.insert_buffer(buf);
+                    this.check_swap(id);
+                    buffers.push((id, loc));
+                },
                 Err(err) => errors.push(err.into()),
             }
         }
 
+        this.recoveries.extend(swap::find_untitled().into_iter().map(|recovery| (None, recovery)));
+
         (this, buffers, errors)
     }
 
+    // Find the buffer of the file at the path, if it is open. Buffers keep the path they were opened with, so paths
+    // are only resolved for comparison.
+    pub fn buffer_with_path(&self, path: &Path) -> Option<BufferId> {
+        let full_path = resolve(path);
+        self.buffers
+            .iter()
+            .find(|(_, (buf, _))| matches!(&buf.path, Some(p) if resolve(p) == full_path))
+            .map(|(id, _)| *id)
+    }
+
     pub fn open_or_create_file(&mut self, path: PathBuf, old_handle: BufferHandle) -> Result<BufferHandle, SharedBufferError> {
-        let full_path = path.canonicalize().unwrap_or(path);
         self
-            .buffers
-            .iter()
-            .find(|(_, (buf, _))| buf.path.as_ref().map(|p| p == &full_path).unwrap_or(false))
-            .map(|(id, _)| Ok(*id))
-            .unwrap_or_else(|| Ok(self.insert_buffer(SharedBuffer::open_or_create(full_path)?)))
+            .buffer_with_path(&path)
+            .map(Ok)
+            .unwrap_or_else(|| {
+                let id = self.insert_buffer(SharedBuffer::open_or_create(path)?);
+                self.check_swap(id);
+                Ok(id)
+            })
             .map(|buf| if old_handle.buffer_id == buf {
                 old_handle
             } else {
@@ -67,6 +114,35 @@ impl State {
             })
     }
 
+    // Look for a swap file containing unsaved content for the buffer's file
+    fn check_swap(&mut self, id: BufferId) {
+        let recovery = match self.get_shared_buffer(id) {
+            Some(buf) => match &buf.path {
+                Some(path) => match swap::find(path) {
+                    // Nothing was lost if the file already contains the recovered content
+                    Some(recovery) if recovery.content.to_string() == buf.content().to_string() => {
+                        swap::discard(path);
+                        None
+                    },
+                    recovery => recovery,
+                },
+                None => None,
+            },
+            None => None,
+        };
+        if let Some(recovery) = recovery {
+            self.recoveries.push((Some(id), recovery));
+        }
+    }
+
+    pub fn next_recovery(&mut self) -> Option<(Option<BufferId>, Recovery)> {
+        if self.recoveries.is_empty() {
+            None
+        } else {
+            Some(self.recoveries.remove(0))
+        }
+    }
+
     pub fn new_handle(&mut self, buffer_id: BufferId) -> Option<BufferHandle> {
         let buf = self.buffers.get_mut(&buffer_id)?;
         let cursor_id = buf.0.insert_cursor(Cursor::default());
@@ -134,6 +210,14 @@ impl State {
         self.recent.iter().any(|b| self.get_buffer(b).map_or(false, |b| b.is_unsaved()))
     }
 
+    // Look for buffers whose files have been changed by other programs
+    pub fn check_files(&mut self) -> Vec<(BufferId, DiskChange)> {
+        self.buffers
+            .iter_mut()
+            .filter_map(|(id, (buf, _))| Some((*id, buf.check_disk()?)))
+            .collect()
+    }
+
     pub fn set_recent_buffer(&mut self, handle: BufferHandle) {
         self.recent.retain(|h| h.buffer_id != handle.buffer_id);
         self.recent.push(handle);
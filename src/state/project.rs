@@ -0,0 +1,267 @@
+use std::{
+    path::{Path, PathBuf},
+    collections::HashMap,
+    ops::Range,
+    fs,
+};
+use ignore::WalkBuilder;
+use crate::buffer::{
+    search::{Query, LineMatch, find_lines_with},
+    shared::SharedBuffer,
+    format::Format,
+    file::resolve,
+    Content,
+};
+use super::{State, StateError};
+
+// Stop searching once this many lines have matched, since nobody will read more
+const MAX_LINES: usize = 10000;
+
+// The matching lines of a single file
+pub struct FileMatches {
+    pub path: PathBuf,
+    pub lines: Vec<LineMatch>,
+}
+
+// A proposed replacement within a group of adjacent lines
+pub struct Hunk {
+    pub line: usize,
+    last_line: usize,
+    // The text of the lines before and after the replacement, without a trailing newline
+    pub old: String,
+    pub new: String,
+    replacements: Vec<(Range<usize>, String)>,
+    pub accepted: bool,
+}
+
+// The proposed replacements within a single file
+pub struct FileReplacements {
+    pub path: PathBuf,
+    // The content that the hunks were made for
+    content: Content,
+    pub hunks: Vec<Hunk>,
+}
+
+// Read a file for searching, skipping anything that doesn't look like text
+fn read_text(path: &Path) -> Option<Content> {
+    let bytes = fs::read(path).ok()?;
+    Format::decode(&bytes).map(|(_, text)| Content::from(text))
+}
+
+// Group replacements into hunks of the lines they touch
+fn make_hunks(content: &Content, replacements: Vec<(Range<usize>, String)>) -> Vec<Hunk> {
+    let mut hunks = Vec::<Hunk>::new();
+    for (range, s) in replacements {
+        let line = content.pos_loc(range.start).y;
+        let last_line = content.pos_loc(range.end).y;
+        match hunks.last_mut() {
+            Some(hunk) if line <= hunk.last_line => {
+                hunk.last_line = hunk.last_line.max(last_line);
+                hunk.replacements.push((range, s));
+            },
+            _ => hunks.push(Hunk {
+                line,
+                last_line,
+                old: String::new(),
+                new: String::new(),
+                replacements: vec![(range, s)],
+                accepted: true,
+            }),
+        }
+    }
+
+    for hunk in hunks.iter_mut() {
+        let start = content.line_pos(hunk.line);
+        let end = content.line_pos(hunk.last_line + 1).saturating_sub(1).min(content.len());
+        let mut at = start;
+        for (range, s) in hunk.replacements.iter() {
+            hunk.new.push_str(&content.slice(at..range.start));
+            hunk.new.push_str(s);
+            at = range.end;
+        }
+        hunk.new.push_str(&content.slice(at.min(end)..end));
+        hunk.old = content.slice(start..end);
+    }
+    hunks
+}
+
+impl State {
+    // Call the function with the content of every file in the directory that isn't ignored (by `.gitignore` and
+    // similar), until it returns false. Files that are open are given as they are in their buffers, unsaved
+    // changes included.
+    fn walk_project(&self, root: &Path, mut f: impl FnMut(PathBuf, &Content) -> bool) {
+        // Buffers keep the path they were opened with, so both sides are resolved to compare them
+        let open = self.buffers
+            .values()
+            .filter_map(|(buf, _)| Some((resolve(buf.path.as_ref()?), buf.content())))
+            .collect::<HashMap<_, _>>();
+
+        for entry in WalkBuilder::new(root).build().filter_map(|e| e.ok()) {
+            if !entry.file_type().map_or(false, |ty| ty.is_file()) {
+                continue;
+            }
+            let path = resolve(entry.path());
+            let more = match open.get(&path) {
+                Some(content) => f(path, content),
+                None => match read_text(&path) {
+                    Some(content) => f(path, &content),
+                    None => true,
+                },
+            };
+            if !more {
+                break;
+            }
+        }
+    }
+
+    pub fn search_project(&self, root: &Path, query: &Query) -> Result<Vec<FileMatches>, regex::Error> {
+        if query.text.is_empty() {
+            return Ok(Vec::new());
+        }
+        let regex = query.compile()?;
+
+        let mut files = Vec::new();
+        let mut line_count = 0;
+        self.walk_project(root, |path, content| {
+            let lines = find_lines_with(&regex, content);
+            if !lines.is_empty() {
+                line_count += lines.len();
+                files.push(FileMatches { path, lines });
+            }
+            line_count < MAX_LINES
+        });
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(files)
+    }
+
+    // Find every replacement that would be made across the project, without making any of them
+    pub fn preview_project_replace(&self, root: &Path, query: &Query, with: &str) -> Result<Vec<FileReplacements>, regex::Error> {
+        if query.text.is_empty() {
+            return Ok(Vec::new());
+        }
+        let regex = query.compile()?;
+
+        let mut files = Vec::new();
+        let mut hunk_count = 0;
+        self.walk_project(root, |path, content| {
+            let replacements = query.replacements_with(&regex, content, 0..content.len(), with);
+            if !replacements.is_empty() {
+                let hunks = make_hunks(content, replacements);
+                hunk_count += hunks.len();
+                files.push(FileReplacements { path, content: content.clone(), hunks });
+            }
+            hunk_count < MAX_LINES
+        });
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(files)
+    }
+
+    // Make the accepted replacements, returning the number of files changed. Open files are edited in their
+    // buffers, so the changes can be undone and are left unsaved. Other files are saved immediately.
+    pub fn apply_project_replace(&mut self, files: &[FileReplacements]) -> (usize, Vec<StateError>) {
+        let mut changed = 0;
+        let mut errors = Vec::new();
+        for file in files {
+            let replacements = file.hunks
+                .iter()
+                .filter(|hunk| hunk.accepted)
+                .flat_map(|hunk| hunk.replacements.iter().cloned())
+                .collect::<Vec<_>>();
+            if replacements.is_empty() {
+                continue;
+            }
+
+            let open = self
+                .buffer_with_path(&file.path)
+                .and_then(|id| self.buffers.get_mut(&id))
+                .map(|(buf, _)| buf);
+            let result = match open {
+                Some(buf) if buf.content() != &file.content => Err(StateError::Changed(file.path.clone())),
+                Some(buf) => {
+                    buf.replace_ranges(&replacements);
+                    Ok(())
+                },
+                None => SharedBuffer::open_or_create(file.path.clone())
+                    .map_err(StateError::from)
+                    .and_then(|mut buf| if buf.content() != &file.content {
+                        Err(StateError::Changed(file.path.clone()))
+                    } else if buf.has_mixed_endings() {
+                        Err(StateError::MixedEndings(file.path.clone()))
+                    } else {
+                        buf.replace_ranges(&replacements);
+                        buf.try_save().map_err(|err| StateError::Shared(err.into()))
+                    }),
+            };
+            match result {
+                Ok(()) => changed += 1,
+                Err(err) => errors.push(err),
+            }
+        }
+        (changed, errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hunks() {
+        let content = Content::from("a x\nb\nx x\nx");
+        let query = Query { text: "x".to_string(), ..Query::default() };
+        let hunks = make_hunks(&content, query.replacements(&content, 0..content.len(), "yz").unwrap());
+
+        let summary = hunks
+            .iter()
+            .map(|h| (h.line, h.old.as_str(), h.new.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, vec![
+            (0, "a x", "a yz"),
+            (2, "x x", "yz yz"),
+            (3, "x", "yz"),
+        ]);
+    }
+
+    #[test]
+    fn search_open_buffers() {
+        // A relative path, as given on the command line
+        let dir = PathBuf::from("target").join(format!("zte-test-search-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "foo\n").unwrap();
+
+        let mut state = State::default();
+        let id = state.insert_buffer(SharedBuffer::open_or_create(dir.join("a.txt")).unwrap());
+        state.get_shared_buffer(id).unwrap().replace_ranges(&[(0..3, "foo foo".to_string())]);
+
+        // Unsaved changes are searched rather than the file on disk
+        let query = Query { text: "foo".to_string(), ..Query::default() };
+        let files = state.search_project(&dir, &query).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].lines[0].text, "foo foo");
+
+        drop(state);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replace_open_buffers() {
+        let dir = PathBuf::from("target").join(format!("zte-test-replace-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.txt"), "foo\n").unwrap();
+
+        let mut state = State::default();
+        let id = state.insert_buffer(SharedBuffer::open_or_create(dir.join("a.txt")).unwrap());
+        state.get_shared_buffer(id).unwrap().replace_ranges(&[(3..3, " foo".to_string())]);
+
+        // The open buffer is edited, unsaved changes and all, and the file is left alone
+        let query = Query { text: "foo".to_string(), ..Query::default() };
+        let files = state.preview_project_replace(&dir, &query, "bar").unwrap();
+        let (changed, errors) = state.apply_project_replace(&files);
+        assert_eq!((changed, errors.len()), (1, 0));
+        assert_eq!(state.get_shared_buffer(id).unwrap().content().to_string(), "bar bar\n");
+        assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "foo\n");
+
+        drop(state);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
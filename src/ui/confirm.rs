@@ -1,9 +1,16 @@
+use std::path::PathBuf;
 use vek::*;
 use crate::{
     Canvas,
     Event,
     Dir,
+    BufferId,
     BufferHandle,
+    Color,
+    buffer::{
+        swap::{self, Recovery},
+        shared::SharedBuffer,
+    },
 };
 use super::{
     Context,
@@ -13,6 +20,13 @@ use super::{
 enum Action {
     Quit,
     CloseBuffer(BufferHandle),
+    // Saving the buffer as the file would replace it
+    Overwrite(PathBuf),
+    // The buffer's file was changed on disk while it had unsaved changes. The diff and scroll offset are present
+    // when the diff is being shown.
+    Reload(BufferId, Option<(Vec<String>, usize)>),
+    // Unsaved content for the buffer (or for an untitled buffer, if there is none) was found in a swap file
+    Recover(Option<BufferId>, Recovery),
 }
 
 pub struct Confirm {
@@ -28,7 +42,29 @@ impl Confirm {
         Self { action: Action::CloseBuffer(buffer) }
     }
 
-    pub fn cancel(self, ctx: &mut Context) {}
+    pub fn overwrite(path: PathBuf) -> Self {
+        Self { action: Action::Overwrite(path) }
+    }
+
+    pub fn reload(buffer: BufferId) -> Self {
+        Self { action: Action::Reload(buffer, None) }
+    }
+
+    pub fn recover(buffer: Option<BufferId>, recovery: Recovery) -> Self {
+        Self { action: Action::Recover(buffer, recovery) }
+    }
+
+    pub fn cancel(self, ctx: &mut Context) {
+        match self.action {
+            // Cancelling keeps the user's changes, since the dialog would otherwise immediately reappear
+            Action::Reload(buffer, _) => if let Some(buf) = ctx.state.get_shared_buffer(buffer) {
+                buf.keep_changes();
+            },
+            // Likewise, declining to recover discards the swap file so that it isn't offered on every launch
+            Action::Recover(_, recovery) => swap::discard(&recovery.file),
+            _ => {},
+        }
+    }
 }
 
 impl Element for Confirm {
@@ -36,13 +72,68 @@ impl Element for Confirm {
 
     fn handle(&mut self, ctx: &mut Context, event: Event) -> Self::Response {
         let recent_count = ctx.state.recent_buffers().len();
-        match (&self.action, event) {
+        match (&mut self.action, event) {
             (Action::Quit, Event::Insert('y')) => return Err(Event::Quit),
             (Action::CloseBuffer(_), Event::Insert('y')) => {
                 ctx.secondary_events.push_back(Event::CloseMenu);
                 return Err(Event::CloseBuffer { force: true })
             },
-            (_, Event::Insert('n')) => ctx.secondary_events.push_back(Event::CloseMenu),
+            (Action::Overwrite(path), Event::Insert('y')) => {
+                ctx.secondary_events.push_back(Event::CloseMenu);
+                return Err(Event::SaveAs { path: path.clone(), force: true })
+            },
+            (Action::Reload(buffer, _), Event::Insert('r')) => {
+                if let Some(buf) = ctx.state.get_shared_buffer(*buffer) {
+                    if let Err(err) = buf.reload() {
+                        ctx.messages.warning(format!("Failed to reload `{}`: {}", buf.title(), err));
+                    }
+                }
+                ctx.secondary_events.push_back(Event::CloseMenu);
+            },
+            (Action::Reload(buffer, _), Event::Insert('k')) => {
+                if let Some(buf) = ctx.state.get_shared_buffer(*buffer) {
+                    buf.keep_changes();
+                }
+                ctx.secondary_events.push_back(Event::CloseMenu);
+            },
+            (Action::Reload(buffer, diff), Event::Insert('d')) => if diff.is_some() {
+                *diff = None;
+            } else if let Some(buf) = ctx.state.get_shared_buffer(*buffer) {
+                match buf.disk_diff() {
+                    Ok(text) => *diff = Some((text.lines().map(|l| l.to_string()).collect(), 0)),
+                    Err(err) => ctx.messages.warning(format!("Failed to diff `{}`: {}", buf.title(), err)),
+                }
+            },
+            (Action::Reload(_, Some((_, scroll))), Event::CursorMove(Dir::Up, _)) => *scroll = scroll.saturating_sub(1),
+            (Action::Reload(_, Some((lines, scroll))), Event::CursorMove(Dir::Down, _)) => *scroll = (*scroll + 1).min(lines.len().saturating_sub(1)),
+            (Action::Recover(Some(buffer), recovery), Event::Insert('r')) => {
+                if let Some(buf) = ctx.state.get_shared_buffer(*buffer) {
+                    buf.recover(&recovery.content);
+                    ctx.messages.warning(format!("Recovered `{}`, save it to keep the changes", buf.title()));
+                }
+                ctx.secondary_events.push_back(Event::CloseMenu);
+            },
+            // Untitled content is recovered into a new buffer, which is journalled under a key of its own
+            (Action::Recover(None, recovery), Event::Insert('r')) => {
+                let buffer = ctx.state.insert_buffer(SharedBuffer::default());
+                if let Some(buf) = ctx.state.get_shared_buffer(buffer) {
+                    buf.recover(&recovery.content);
+                    ctx.messages.warning("Recovered an untitled buffer, save it to keep the changes");
+                }
+                swap::discard(&recovery.file);
+                if let Some(handle) = ctx.state.new_handle(buffer) {
+                    ctx.secondary_events.push_back(Event::SwitchBuffer(handle));
+                }
+                ctx.secondary_events.push_back(Event::CloseMenu);
+            },
+            (Action::Recover(_, recovery), Event::Insert('d' | 'n')) => {
+                swap::discard(&recovery.file);
+                ctx.secondary_events.push_back(Event::CloseMenu);
+            },
+            // Other actions need `cancel` to run when they are dismissed, which happens on escape
+            (Action::Quit | Action::CloseBuffer(_) | Action::Overwrite(_), Event::Insert('n')) => {
+                ctx.secondary_events.push_back(Event::CloseMenu);
+            },
             (_, event @ Event::Escape) => return Err(event),
             (_, _) => {},
         }
@@ -55,11 +146,16 @@ impl Element for Confirm {
 
     fn render(&self, ctx: &mut Context, canvas: &mut impl Canvas, active: bool) {
         let sz = canvas.size();
+        // Diffs need more room
+        let height = match &self.action {
+            Action::Reload(_, Some(_)) => sz.h / 3,
+            _ => sz.h / 6,
+        };
         let mut canvas = canvas.window(Rect::new(
             (sz.w / 2).saturating_sub(sz.w / 3),
-            (sz.h / 2).saturating_sub(sz.h / 6),
+            (sz.h / 2).saturating_sub(height),
             sz.w * 2 / 3,
-            sz.h * 2 / 6,
+            height * 2,
         ));
 
         // Frame
@@ -93,10 +189,40 @@ impl Element for Confirm {
                 "Close `{}` and lose unsaved changes? (y/n)",
                 ctx.state.get_buffer(&buffer).map_or("", |b| b.title()),
             ),
+            Action::Overwrite(path) => format!("`{}` already exists. Overwrite it? (y/n)", path.display()),
+            Action::Reload(buffer, diff) => format!(
+                "`{}` was changed on disk but has unsaved changes.\n\n(r)eload / (k)eep mine / ({}) diff",
+                ctx.state.get_shared_buffer(*buffer).map_or("", |b| b.title()),
+                if diff.is_some() { "d: hide" } else { "d: show" },
+            ),
+            Action::Recover(buffer, recovery) => format!(
+                "Unsaved changes to `{}` from {} minutes ago were found after a crash.\n\n(r)ecover / (d)iscard",
+                buffer.and_then(|b| ctx.state.get_shared_buffer(b)).map_or("untitled", |b| b.title()),
+                recovery.time.elapsed().map_or(0, |d| d.as_secs() / 60),
+            ),
         };
         for (i, line) in text.lines().enumerate() {
             let x = canvas.size().w.saturating_sub(line.chars().count()) / 2;
             canvas.write_str(Vec2::new(x, i + 1), line);
         }
+
+        if let Action::Reload(_, Some((lines, scroll))) = &self.action {
+            let offset = text.lines().count() + 2;
+            for (y, line) in lines
+                .iter()
+                .skip(*scroll)
+                .take(canvas.size().h.saturating_sub(offset))
+                .enumerate()
+            {
+                let color = if line.starts_with('+') {
+                    ctx.theme.create_color
+                } else if line.starts_with('-') {
+                    ctx.theme.invalid_color
+                } else {
+                    Color::Reset
+                };
+                canvas.with_fg(color).write_str(Vec2::new(1, offset + y), line);
+            }
+        }
     }
 }
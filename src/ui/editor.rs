@@ -6,11 +6,12 @@ use crate::{
     Line,
     Event,
     Dir,
-    buffer::{highlight::Highlights, shared::SharedBuffer},
+    buffer::shared::SharedBuffer,
 };
 use super::{
     Context,
     Element,
+    Severity,
 };
 
 const MARGIN_WIDTH: usize = 5;
@@ -52,6 +53,15 @@ impl Editor {
     pub fn buffer(&self) -> &BufferHandle {
         &self.buffer
     }
+
+    // Move the cursor to a line and a character (not glyph) column within it, showing it in the middle of the editor
+    pub fn jump_to(&mut self, ctx: &mut Context, loc: Vec2<usize>) {
+        let mut buf = ctx.state
+            .get_buffer_mut(&self.buffer)
+            .unwrap();
+        buf.go_to_loc(loc);
+        self.loc.y = buf.cursor_loc().y.saturating_sub(self.page_height / 2);
+    }
 }
 
 impl Element for Editor {
@@ -62,6 +72,11 @@ impl Element for Editor {
             .get_buffer_mut(&self.buffer)
             .unwrap();
 
+        // Rectangular selections only last while they're being extended
+        if !matches!(event, Event::BlockSelect(_)) {
+            buf.clear_block();
+        }
+
         match event {
             Event::CloseBuffer { force } => if !force && ctx.state
                 .get_buffer(&self.buffer)
@@ -83,17 +98,66 @@ impl Element for Editor {
                 let buf = ctx.state.insert_buffer(SharedBuffer::default());
                 self.buffer = ctx.state.new_handle(buf).unwrap();
             },
-            Event::SaveBuffer => buf.try_save().unwrap(),
+            // Untitled buffers need to be given a path first
+            Event::SaveBuffer if buf.path().is_none() => ctx.secondary_events.push_back(Event::OpenSaveAs),
+            Event::SaveBuffer => match (buf.has_mixed_endings(), buf.try_save()) {
+                (true, Ok(())) => ctx.messages.warning(format!("Saved `{}`, making its mixed line endings uniform", buf.title())),
+                (_, Ok(())) => ctx.messages.push(Severity::Info, format!("Saved `{}`", buf.title())),
+                (_, Err(err)) => ctx.messages.error(format!("Failed to save `{}`: {}", buf.title(), err)),
+            },
+            Event::SaveAs { path, force } => match ctx.state.buffer_with_path(&path) {
+                // Two buffers must never be saved to the same file
+                Some(other) if other != self.buffer.buffer_id => {
+                    ctx.messages.error(format!("`{}` is already open in another buffer", path.display()));
+                },
+                // Replacing a file other than the buffer's own needs confirmation
+                None if !force && path.exists() => ctx.secondary_events.push_back(Event::ConfirmOverwrite(path)),
+                _ => {
+                    let mut buf = ctx.state.get_buffer_mut(&self.buffer).unwrap();
+                    match buf.save_as(path) {
+                        Ok(()) => ctx.messages.push(Severity::Info, format!("Saved as `{}`", buf.title())),
+                        Err(err) => ctx.messages.error(format!("Failed to save `{}`: {}", buf.title(), err)),
+                    }
+                },
+            },
+            Event::ToggleLineEnding => buf.toggle_line_ending(),
+            Event::CycleIndent => {
+                buf.cycle_indent();
+                ctx.messages.push(Severity::Info, format!("Indenting `{}` with {}", buf.title(), buf.config().describe_indent()));
+            },
             Event::SwitchBuffer(buffer) => self.buffer = buffer,
-            Event::PageMove(dir, reach) => buf.do_cursor_movement(dir, reach, |b| { b.cursor_move(dir, self.page_height); }),
+            Event::PageMove(dir, reach) => {
+                let page_height = self.page_height;
+                buf.for_each_cursor(|b| b.do_cursor_movement(dir, reach, |b| { b.cursor_move(dir, page_height); }));
+            },
+            Event::ClearCursors => buf.clear_extra_cursors(),
+            Event::GoTo(loc) => {
+                buf.go_to_loc(loc);
+                // Show the new location in the middle of the editor
+                self.loc.y = buf.cursor_loc().y.saturating_sub(self.page_height / 2);
+            },
             Event::Undo => buf.undo(),
             Event::Redo => buf.redo(),
+            Event::SwitchUndoBranch(dir) => buf.undo_branch(dir),
             Event::NewFile(path) | Event::OpenFile(path) => match ctx
                 .state
                 .open_or_create_file(path, self.buffer.clone())
             {
                 Ok(buf) => self.buffer = buf,
-                Err(err) => log::warn!("When opening file: {:?}", err),
+                Err(err) => ctx.messages.error(format!("Failed to open file: {}", err)),
+            },
+            Event::OpenFileAt(path, loc) => match ctx
+                .state
+                .open_or_create_file(path, self.buffer.clone())
+            {
+                Ok(buf) => {
+                    self.buffer = buf;
+                    self.jump_to(ctx, loc);
+                },
+                Err(err) => ctx.messages.error(format!("Failed to open file: {}", err)),
+            },
+            Event::Comment => if !buf.toggle_comment() {
+                ctx.messages.push(Severity::Info, format!("`{}` has no known comment syntax", buf.title()));
             },
             event => buf.handle(event)?,
         }
@@ -127,7 +191,7 @@ impl Element for Editor {
 
     fn render(&self, ctx: &mut Context, canvas: &mut impl Canvas, active: bool) {
         let sz = canvas.size();
-        let buf = ctx.state
+        let mut buf = ctx.state
             .get_buffer_mut(&self.buffer)
             .unwrap();
 
@@ -141,14 +205,39 @@ impl Element for Editor {
             .frame();
 
         // Title
-        let title = format!("[ {}{} ]", if buf.is_unsaved() { "*" } else { "" }, buf.title());
+        let title = format!(
+            "[ {}{} ({}, {}) ]",
+            if buf.is_unsaved() { "*" } else { "" },
+            buf.title(),
+            buf.describe_format(),
+            buf.config().describe_indent(),
+        );
         canvas.write_str(Vec2::new((sz.w.saturating_sub(title.len())) / 2, 0), &title);
 
         let mut canvas = canvas.window(Rect::new(1, 1, canvas.size().w.saturating_sub(2), canvas.size().h.saturating_sub(2)));
 
-        let highlights = Highlights::from_file(buf.path(), &buf.get_string());
+        let highlights = buf.highlights();
 
         let cursor_loc = buf.pos_loc(buf.cursor().pos);
+        let cursors = buf.cursors();
+        let extra_cursor_locs = cursors[1..].iter().map(|c| buf.pos_loc(c.pos)).collect::<Vec<_>>();
+        let search_matches = ctx.search_matches
+            .as_ref()
+            .filter(|(id, _)| *id == self.buffer.buffer_id)
+            .map_or(&[][..], |(_, matches)| &matches[..]);
+        let is_match = |pos: usize| search_matches
+            .binary_search_by(|m| if m.end <= pos {
+                std::cmp::Ordering::Less
+            } else if m.start > pos {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            })
+            .is_ok();
+        // Rectangular selections are drawn in full, even beyond the ends of lines, since the glyphs of each line are
+        // padded with blank cells to the edge of the editor
+        let block = buf.block().map(|(a, b)| (a.map2(b, usize::min), a.map2(b, usize::max)));
+        let brackets = buf.matching_bracket();
 
         for row in 0..canvas.size().h {
             let buf_row = row + self.loc.y;
@@ -164,7 +253,7 @@ impl Element for Editor {
                 .chars()
                 .enumerate()
             {
-                let line_selected = buf.cursor().encloses(buf_row_pos) && row < buf.content().lines().len();
+                let line_selected = cursors.iter().any(|c| c.encloses(buf_row_pos)) && row < buf.content().lines().len();
                 canvas
                     .with_fg(if line_selected {
                         Color::Rgb(Rgb::broadcast(255))
@@ -189,8 +278,23 @@ impl Element for Editor {
                 let buf_col = col + self.loc.x;
                 let buf_pos = buf_row_pos + line_pos.unwrap_or(line.len().saturating_sub(1));
 
-                let bg_color = if buf.cursor().inside_reach(buf_pos) /*&& line_pos.is_some()*/ {
+                let bg_color = if extra_cursor_locs.contains(&Vec2::new(buf_col, buf_row)) {
+                    // Only the primary cursor can be shown by the terminal, so extra ones are drawn
+                    ctx.theme.cursor_color
+                } else if let Some((min, max)) = block {
+                    if (min.x..max.x).contains(&buf_col) && (min.y..=max.y).contains(&buf_row) {
+                        ctx.theme.selection_color
+                    } else if buf_row == cursor_loc.y {
+                        ctx.theme.subtle_bg_color
+                    } else {
+                        Color::Reset
+                    }
+                } else if cursors.iter().any(|c| c.inside_reach(buf_pos)) /*&& line_pos.is_some()*/ {
                     ctx.theme.selection_color
+                } else if line_pos.is_some() && is_match(buf_pos) {
+                    ctx.theme.search_color
+                } else if line_pos.is_some() && brackets.map_or(false, |(a, b)| buf_pos == a || buf_pos == b) {
+                    ctx.theme.bracket_color
                 } else if buf_row == cursor_loc.y {
                     ctx.theme.subtle_bg_color
                 } else {
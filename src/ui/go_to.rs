@@ -0,0 +1,154 @@
+use vek::*;
+use crate::{
+    Canvas,
+    Event,
+    BufferHandle,
+    Color,
+    Cursor,
+};
+use super::{
+    Context,
+    Element,
+    Prompt,
+};
+
+// Parse a location of the form `123`, `123:45`, `+10`, `-10` or `%50`, relative to the current line. Lines and
+// columns are numbered from 1, but the location is returned numbered from 0.
+fn parse(s: &str, current_line: usize, line_count: usize) -> Option<Vec2<usize>> {
+    let mut parts = s.trim().splitn(2, ':');
+    let line_spec = parts.next()?;
+    let col = match parts.next() {
+        Some(col) => col.trim().parse::<usize>().ok()?.saturating_sub(1),
+        None => 0,
+    };
+
+    let last_line = line_count.saturating_sub(1);
+    let line = if let Some(n) = line_spec.strip_prefix('+') {
+        current_line + n.parse::<usize>().ok()?
+    } else if let Some(n) = line_spec.strip_prefix('-') {
+        current_line.saturating_sub(n.parse::<usize>().ok()?)
+    } else if let Some(n) = line_spec.strip_prefix('%') {
+        last_line * n.parse::<usize>().ok()?.min(100) / 100
+    } else {
+        line_spec.parse::<usize>().ok()?.saturating_sub(1)
+    };
+
+    Some(Vec2::new(col, line.min(last_line)))
+}
+
+pub struct GoTo {
+    prompt: Prompt,
+    buffer: BufferHandle,
+    prev_cursor: Cursor,
+    current_line: usize,
+    line_count: usize,
+    invalid: bool,
+}
+
+impl GoTo {
+    pub fn new(ctx: &mut Context, buffer: BufferHandle) -> Self {
+        let buf = ctx.state
+            .get_buffer_mut(&buffer)
+            .unwrap();
+        let prev_cursor = *buf.cursor();
+        let current_line = buf.cursor_loc().y;
+        let line_count = buf.line_count();
+
+        Self {
+            prompt: Prompt::default(),
+            buffer,
+            prev_cursor,
+            current_line,
+            line_count,
+            invalid: false,
+        }
+    }
+
+    pub fn cancel(self, ctx: &mut Context) {
+        if let Some(mut buf) = ctx.state.get_buffer_mut(&self.buffer) {
+            *buf.cursor_mut() = self.prev_cursor;
+        }
+    }
+}
+
+impl Element for GoTo {
+    type Response = Result<(), Event>;
+
+    fn handle(&mut self, ctx: &mut Context, event: Event) -> Self::Response {
+        match event {
+            Event::Insert('\n') => ctx.secondary_events.push_back(Event::CloseMenu),
+            // Handled by the editor
+            event @ Event::GoTo(_) => return Err(event),
+            event => {
+                let old_text = self.prompt.get_text();
+                self.prompt.handle(ctx, event)?;
+                let text = self.prompt.get_text();
+                if text != old_text {
+                    // Jump as the location is typed, so that it can be previewed
+                    match parse(&text, self.current_line, self.line_count) {
+                        Some(loc) => {
+                            self.invalid = false;
+                            ctx.secondary_events.push_back(Event::GoTo(loc));
+                        },
+                        None => self.invalid = !text.is_empty(),
+                    }
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn update(&mut self, ctx: &mut Context, canvas: &mut impl Canvas, active: bool) {
+        self.prompt.set_fg_color(if self.invalid {
+            ctx.theme.invalid_color
+        } else {
+            Color::Rgb(Rgb::new(255, 255, 255))
+        });
+
+        self.prompt.update(ctx, canvas, active);
+    }
+
+    fn render(&self, ctx: &mut Context, canvas: &mut impl Canvas, active: bool) {
+        let sz = canvas.size();
+        let mut canvas = canvas.window(Rect::new(
+            sz.w / 3,
+            sz.h.saturating_sub(4),
+            sz.w.saturating_sub(sz.w * 2 / 3),
+            3,
+        ));
+
+        // Frame
+        let sz = canvas.size();
+        canvas.rectangle(Vec2::zero(), sz, ' ');
+        canvas.frame();
+
+        let title = format!("[Go To Line {}/{}]", self.current_line + 1, self.line_count);
+        canvas.write_str(Vec2::new((sz.w.saturating_sub(title.len())) / 2, 0), &title);
+
+        self.prompt.render(ctx, &mut canvas.window(Rect::new(
+            2,
+            1,
+            sz.w.saturating_sub(4),
+            1,
+        )), active);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locations() {
+        let parse = |s| parse(s, 49, 200);
+        assert_eq!(parse("123"), Some(Vec2::new(0, 122)));
+        assert_eq!(parse("123:45"), Some(Vec2::new(44, 122)));
+        assert_eq!(parse("+10"), Some(Vec2::new(0, 59)));
+        assert_eq!(parse("-10"), Some(Vec2::new(0, 39)));
+        assert_eq!(parse("-100"), Some(Vec2::new(0, 0)));
+        assert_eq!(parse("%50"), Some(Vec2::new(0, 99)));
+        assert_eq!(parse("1000"), Some(Vec2::new(0, 199)));
+        assert_eq!(parse("abc"), None);
+        assert_eq!(parse(""), None);
+    }
+}
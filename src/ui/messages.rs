@@ -0,0 +1,111 @@
+use std::{
+    collections::VecDeque,
+    time::{Instant, Duration},
+    fmt,
+};
+use vek::*;
+use crate::{
+    Canvas,
+    Color,
+    buffer::highlight::Region,
+};
+use super::Theme;
+
+// The most messages that will be shown at once, older ones are dropped
+const MAX_MESSAGES: usize = 4;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    // How long a message of this severity stays on screen
+    fn lifetime(&self) -> Duration {
+        match self {
+            Severity::Info => Duration::from_secs(3),
+            Severity::Warning => Duration::from_secs(5),
+            Severity::Error => Duration::from_secs(8),
+        }
+    }
+
+    fn color(&self, theme: &Theme) -> Color {
+        theme.get_highlight_color(match self {
+            Severity::Info => Region::Info,
+            Severity::Warning => Region::Warning,
+            Severity::Error => Region::Error,
+        })
+    }
+}
+
+pub struct Message {
+    severity: Severity,
+    text: String,
+    time: Instant,
+}
+
+#[derive(Default)]
+pub struct Messages {
+    messages: VecDeque<Message>,
+}
+
+impl Messages {
+    pub fn push(&mut self, severity: Severity, text: impl fmt::Display) {
+        log::info!("{:?}: {}", severity, text);
+        self.messages.push_back(Message {
+            severity,
+            text: text.to_string(),
+            time: Instant::now(),
+        });
+        while self.messages.len() > MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+    }
+
+    pub fn warning(&mut self, text: impl fmt::Display) {
+        self.push(Severity::Warning, text);
+    }
+
+    pub fn error(&mut self, text: impl fmt::Display) {
+        self.push(Severity::Error, text);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.messages.clear();
+    }
+
+    // Remove messages that have been visible for long enough
+    pub fn update(&mut self) {
+        let now = Instant::now();
+        self.messages.retain(|msg| now.saturating_duration_since(msg.time) < msg.severity.lifetime());
+    }
+
+    pub fn render(&self, theme: &Theme, canvas: &mut impl Canvas) {
+        let sz = canvas.size();
+        let width = self.messages
+            .iter()
+            .map(|msg| msg.text.chars().count() + 2)
+            .max()
+            .unwrap_or(0)
+            .min(sz.w);
+
+        // Messages stack upwards from the bottom right corner, newest at the bottom
+        for (i, msg) in self.messages.iter().rev().enumerate() {
+            let y = match sz.h.checked_sub(2 + i) {
+                Some(y) => y,
+                None => break,
+            };
+            let mut canvas = canvas.window(Rect::new(sz.w.saturating_sub(width + 1), y, width, 1));
+            let mut fg = canvas.with_fg(Color::Rgb(Rgb::new(255, 255, 255)));
+            let mut canvas = fg.with_bg(msg.severity.color(theme));
+            canvas.rectangle(Vec2::zero(), Extent2::new(width, 1), ' ');
+            canvas.write_str(Vec2::new(1, 0), &msg.text);
+        }
+    }
+}
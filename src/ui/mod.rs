@@ -6,32 +6,56 @@ mod switcher;
 mod confirm;
 mod opener;
 mod prompt;
+mod undo_tree;
+mod messages;
+mod search;
+mod project_search;
+mod go_to;
 
 // Reexports
 pub use self::{
     theme::Theme,
     editor::Editor,
     terminal::Terminal,
-    panels::{Panels, Tile},
+    panels::{Panels, Tile, Split},
     switcher::Switcher,
     confirm::Confirm,
     opener::Opener,
     prompt::Prompt,
+    undo_tree::UndoTree,
+    messages::{Messages, Severity},
+    search::Search,
+    project_search::ProjectSearch,
+    go_to::GoTo,
 };
 
-use std::collections::VecDeque;
+use std::{
+    collections::VecDeque,
+    time::{Instant, Duration},
+    ops::Range,
+};
+use vek::*;
 use crate::{
     Canvas,
     Event,
     State,
-    buffer::BufferId,
+    buffer::{BufferId, shared::DiskChange, swap, search::Query},
 };
 
+// How often open files are checked for changes made by other programs
+const FILE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+// How often unsaved changes are written to swap files
+const SWAP_INTERVAL: Duration = Duration::from_secs(5);
+
 pub struct Context {
     theme: Theme,
     state: State,
     active_buffer: BufferId,
     secondary_events: VecDeque<Event>,
+    messages: Messages,
+    last_search: Option<Query>,
+    // Search matches to highlight in a buffer while searching
+    search_matches: Option<(BufferId, Vec<Range<usize>>)>,
 }
 
 pub trait Element {
@@ -46,29 +70,38 @@ pub struct MainUi {
     ctx: Context,
     panels: Panels,
     menu: Option<Menu>,
+    last_file_check: Instant,
+    last_swap: Instant,
 }
 
 impl MainUi {
-    pub fn new(theme: Theme, state: State, buffers: Vec<BufferId>) -> Self {
+    pub fn new(theme: Theme, state: State, buffers: Vec<(BufferId, Option<Vec2<usize>>)>, split: Split) -> Self {
         let mut ctx = Context {
             theme,
             state,
             active_buffer: BufferId(0), // Gets replaced later
             secondary_events: VecDeque::new(),
+            messages: Messages::default(),
+            last_search: None,
+            search_matches: None,
         };
 
         let panels = match buffers.len() {
             0 => Panels::empty(&mut ctx, 1),
             _ => {
-                let mut panels = Panels::empty(&mut ctx, 0);
-
-                for buffer in buffers.into_iter().rev() {
-                    panels.insert_column(0, Tile::Editor(Editor::from(ctx.state
-                        .new_handle(buffer)
-                        .unwrap())));
-                }
-
-                panels
+                let tiles = buffers
+                    .into_iter()
+                    .map(|(buffer, loc)| {
+                        let mut editor = Editor::from(ctx.state
+                            .new_handle(buffer)
+                            .unwrap());
+                        if let Some(loc) = loc {
+                            editor.jump_to(&mut ctx, loc);
+                        }
+                        Tile::Editor(editor)
+                    })
+                    .collect();
+                Panels::from_tiles(tiles, split)
             },
         };
 
@@ -78,6 +111,8 @@ impl MainUi {
             ctx,
             panels,
             menu: None,
+            last_file_check: Instant::now(),
+            last_swap: Instant::now(),
         }
     }
 
@@ -89,18 +124,26 @@ impl MainUi {
                     Some(Menu::Switcher(switcher)) => Ok(switcher.cancel(&mut self.ctx)),
                     Some(Menu::Confirm(confirm)) => Ok(confirm.cancel(&mut self.ctx)),
                     Some(Menu::Opener(_)) => Ok(()),
+                    Some(Menu::UndoTree(undo_tree)) => Ok(undo_tree.cancel(&mut self.ctx)),
+                    Some(Menu::Search(search)) => Ok(search.cancel(&mut self.ctx)),
+                    Some(Menu::GoTo(go_to)) => Ok(go_to.cancel(&mut self.ctx)),
                     None => Err(event),
                 },
                 event => match menu {
                     Menu::Switcher(switcher) => switcher.handle(&mut self.ctx, event),
                     Menu::Opener(opener) => opener.handle(&mut self.ctx, event),
                     Menu::Confirm(confirm) => confirm.handle(&mut self.ctx, event),
+                    Menu::UndoTree(undo_tree) => undo_tree.handle(&mut self.ctx, event),
+                    Menu::Search(search) => search.handle(&mut self.ctx, event),
+                    Menu::GoTo(go_to) => go_to.handle(&mut self.ctx, event),
                 },
             },
             None => Err(event)
         } {
             match event {
                 Event::Quit => return true,
+                Event::Escape if !self.ctx.messages.is_empty() => self.ctx.messages.clear(),
+                Event::Escape if self.active_has_extra_cursors() => self.panels.handle(&mut self.ctx, Event::ClearCursors),
                 Event::Escape if !self.ctx.state.any_unsaved() => return true,
                 Event::Escape => self.menu = Some(Menu::Confirm(Confirm::quit(&mut self.ctx))),
                 Event::OpenPrompt => unimplemented!(),
@@ -111,8 +154,38 @@ impl MainUi {
                     ))),
                     _ => {},
                 },
+                Event::OpenUndoTree => match self.panels.active_mut().and_then(|col| col.active_mut()) {
+                    Some(Tile::Editor(editor)) => self.menu = Some(Menu::UndoTree(UndoTree::new(
+                        &mut self.ctx,
+                        editor.buffer().clone(),
+                    ))),
+                    _ => {},
+                },
+                Event::OpenSearch => match self.panels.active_mut().and_then(|col| col.active_mut()) {
+                    Some(Tile::Editor(editor)) => self.menu = Some(Menu::Search(Search::new(
+                        &mut self.ctx,
+                        editor.buffer().clone(),
+                    ))),
+                    _ => {},
+                },
+                Event::OpenReplace => match self.panels.active_mut().and_then(|col| col.active_mut()) {
+                    Some(Tile::Editor(editor)) => self.menu = Some(Menu::Search(Search::replace(
+                        &mut self.ctx,
+                        editor.buffer().clone(),
+                    ))),
+                    _ => {},
+                },
+                Event::OpenGoTo => match self.panels.active_mut().and_then(|col| col.active_mut()) {
+                    Some(Tile::Editor(editor)) => self.menu = Some(Menu::GoTo(GoTo::new(
+                        &mut self.ctx,
+                        editor.buffer().clone(),
+                    ))),
+                    _ => {},
+                },
                 Event::OpenOpener => self.menu = Some(Menu::Opener(Opener::new(&mut self.ctx))),
+                Event::OpenSaveAs => self.menu = Some(Menu::Opener(Opener::save_as(&mut self.ctx))),
                 Event::ConfirmCloseBuffer(buffer) => self.menu = Some(Menu::Confirm(Confirm::close_buffer(buffer))),
+                Event::ConfirmOverwrite(path) => self.menu = Some(Menu::Confirm(Confirm::overwrite(path))),
                 event => self.panels.handle(&mut self.ctx, event),
             }
         }
@@ -124,13 +197,62 @@ impl MainUi {
         false
     }
 
+    fn active_has_extra_cursors(&mut self) -> bool {
+        match self.panels.active_mut().and_then(|col| col.active_mut()) {
+            Some(Tile::Editor(editor)) => self.ctx.state
+                .get_buffer_mut(editor.buffer())
+                .map_or(false, |buf| buf.has_extra_cursors()),
+            _ => false,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        if self.last_swap.elapsed() >= SWAP_INTERVAL {
+            self.last_swap = Instant::now();
+            swap::flush();
+        }
+
+        if self.menu.is_none() {
+            if let Some((id, recovery)) = self.ctx.state.next_recovery() {
+                self.menu = Some(Menu::Confirm(Confirm::recover(id, recovery)));
+            }
+        }
+
+        if self.last_file_check.elapsed() < FILE_CHECK_INTERVAL {
+            return;
+        }
+        self.last_file_check = Instant::now();
+
+        for (id, change) in self.ctx.state.check_files() {
+            match change {
+                DiskChange::Reloaded => {
+                    let title = self.ctx.state.get_shared_buffer(id).map_or("", |b| b.title()).to_string();
+                    self.ctx.messages.push(Severity::Info, format!("Reloaded `{}`", title));
+                },
+                // Conflicts keep being detected until resolved, so they can wait for any open menu to close
+                DiskChange::Conflict => if self.menu.is_none() {
+                    self.menu = Some(Menu::Confirm(Confirm::reload(id)));
+                },
+            }
+        }
+    }
+
+    pub fn messages(&mut self) -> &mut Messages {
+        &mut self.ctx.messages
+    }
+
     pub fn update(&mut self, canvas: &mut impl Canvas) {
+        self.ctx.messages.update();
+        self.ctx.search_matches = None;
         self.panels.update(&mut self.ctx, canvas, self.menu.is_none());
 
         match &mut self.menu {
             Some(Menu::Switcher(switcher)) => switcher.update(&mut self.ctx, canvas, true),
             Some(Menu::Opener(opener)) => opener.update(&mut self.ctx, canvas, true),
             Some(Menu::Confirm(confirm)) => confirm.update(&mut self.ctx, canvas, true),
+            Some(Menu::UndoTree(undo_tree)) => undo_tree.update(&mut self.ctx, canvas, true),
+            Some(Menu::Search(search)) => search.update(&mut self.ctx, canvas, true),
+            Some(Menu::GoTo(go_to)) => go_to.update(&mut self.ctx, canvas, true),
             None => {},
         }
     }
@@ -142,8 +264,13 @@ impl MainUi {
             Some(Menu::Switcher(switcher)) => switcher.render(&mut self.ctx, canvas, true),
             Some(Menu::Opener(opener)) => opener.render(&mut self.ctx, canvas, true),
             Some(Menu::Confirm(confirm)) => confirm.render(&mut self.ctx, canvas, true),
+            Some(Menu::UndoTree(undo_tree)) => undo_tree.render(&mut self.ctx, canvas, true),
+            Some(Menu::Search(search)) => search.render(&mut self.ctx, canvas, true),
+            Some(Menu::GoTo(go_to)) => go_to.render(&mut self.ctx, canvas, true),
             None => {},
         }
+
+        self.ctx.messages.render(&self.ctx.theme, canvas);
     }
 }
 
@@ -151,4 +278,7 @@ pub enum Menu {
     Switcher(Switcher),
     Opener(Opener),
     Confirm(Confirm),
+    UndoTree(UndoTree),
+    Search(Search),
+    GoTo(GoTo),
 }
@@ -17,7 +17,15 @@ use super::{
     Prompt,
 };
 
+// What the chosen path will be used for
+#[derive(Copy, Clone, PartialEq)]
+enum Mode {
+    Open,
+    SaveAs,
+}
+
 pub struct Opener {
+    mode: Mode,
     prompt: Prompt,
     path: PathBuf,
     listings: Option<(usize, Vec<DirEntry>)>,
@@ -25,13 +33,24 @@ pub struct Opener {
 
 impl Opener {
     pub fn new(ctx: &mut Context) -> Self {
+        Self::with_mode(ctx, Mode::Open)
+    }
+
+    pub fn save_as(ctx: &mut Context) -> Self {
+        Self::with_mode(ctx, Mode::SaveAs)
+    }
+
+    fn with_mode(ctx: &mut Context, mode: Mode) -> Self {
         let mut this = Self {
+            mode,
             prompt: Prompt::default(),
             path: ctx.state
                 .get_shared_buffer(ctx.active_buffer)
+                // A file opened by a bare name, like `foo.rs`, has an empty parent
                 .and_then(|buf| buf.path
                     .as_ref()
                     .and_then(|p| p.parent())
+                    .filter(|p| !p.as_os_str().is_empty())
                     .map(|p| p.to_owned()))
                 .or_else(|| std::env::current_dir().ok())
                 .unwrap_or_default(),
@@ -116,7 +135,10 @@ impl Element for Opener {
 
                         if is_file {
                             ctx.secondary_events.push_back(Event::CloseMenu);
-                            ctx.secondary_events.push_back(Event::OpenFile(self.path.clone()));
+                            ctx.secondary_events.push_back(match self.mode {
+                                Mode::Open => Event::OpenFile(self.path.clone()),
+                                Mode::SaveAs => Event::SaveAs { path: self.path.clone(), force: false },
+                            });
                         }
 
                         self.prompt = Prompt::default();
@@ -124,7 +146,10 @@ impl Element for Opener {
                     } else {
                         ctx.secondary_events.push_back(Event::CloseMenu);
                         self.path.push(self.prompt.get_text());
-                        ctx.secondary_events.push_back(Event::NewFile(self.path.clone()));
+                        ctx.secondary_events.push_back(match self.mode {
+                            Mode::Open => Event::NewFile(self.path.clone()),
+                            Mode::SaveAs => Event::SaveAs { path: self.path.clone(), force: false },
+                        });
                     }
                 }
             },
@@ -187,14 +212,17 @@ impl Element for Opener {
         canvas.rectangle(Vec2::zero(), sz, ' '.into());
         canvas.frame();
 
-        let title = format!("[Open File]");
-        canvas.write_str(Vec2::new((sz.w.saturating_sub(title.len())) / 2, 0), &title);
+        let title = match self.mode {
+            Mode::Open => "[Open File]",
+            Mode::SaveAs => "[Save As]",
+        };
+        canvas.write_str(Vec2::new((sz.w.saturating_sub(title.len())) / 2, 0), title);
 
         const DIR_COLOR: Color = Color::Rgb(Rgb::new(255, 200, 100));
 
         // Prompt
         let mut path_text = format!("{}", self.path.display());
-        if path_text.chars().nth(path_text.len() - 1).map(|c| c != '/').unwrap_or(false) {
+        if !path_text.is_empty() && !path_text.ends_with('/') {
             path_text.push('/');
         }
         let mut canvas = canvas.window(Rect::new(1, 1, canvas.size().w - 2, canvas.size().h - 2));
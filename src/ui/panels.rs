@@ -3,6 +3,7 @@ use super::{
     Element,
     Editor,
     Terminal,
+    ProjectSearch,
     Context,
 };
 use crate::{
@@ -14,6 +15,16 @@ use crate::{
 pub enum Tile {
     Editor(Editor),
     Terminal(Terminal),
+    ProjectSearch(ProjectSearch),
+}
+
+// How multiple tiles are laid out
+#[derive(Copy, Clone, Debug)]
+pub enum Split {
+    // Side by side
+    Vertical,
+    // One above another
+    Horizontal,
 }
 
 pub struct Column {
@@ -62,6 +73,10 @@ impl Column {
         }
     }
 
+    fn find_editor(&self) -> Option<usize> {
+        self.tiles.iter().position(|tile| matches!(tile, Tile::Editor(_)))
+    }
+
     pub fn close_editor(&mut self) -> bool {
         if self.tiles.len() > 1 {
             self.tiles.remove(self.active_idx);
@@ -97,6 +112,7 @@ impl Element for Column {
             event => match self.active_mut() {
                 Some(Tile::Editor(editor)) => editor.handle(ctx, event)?,
                 Some(Tile::Terminal(terminal)) => terminal.handle(ctx, event),
+                Some(Tile::ProjectSearch(search)) => search.handle(ctx, event)?,
                 None => {},
             },
         }
@@ -109,6 +125,7 @@ impl Element for Column {
             match &mut self.tiles[idx] {
                 Tile::Editor(editor) => editor.update(ctx, &mut canvas.window(tile_area), active && idx == self.active_idx),
                 Tile::Terminal(terminal) => terminal.update(ctx, &mut canvas.window(tile_area), active && idx == self.active_idx),
+                Tile::ProjectSearch(search) => search.update(ctx, &mut canvas.window(tile_area), active && idx == self.active_idx),
             }
         }
     }
@@ -119,6 +136,7 @@ impl Element for Column {
             match tile {
                 Tile::Editor(editor) => editor.render(ctx, &mut canvas.window(tile_area), active && idx == self.active_idx),
                 Tile::Terminal(terminal) => terminal.render(ctx, &mut canvas.window(tile_area), active && idx == self.active_idx),
+                Tile::ProjectSearch(search) => search.render(ctx, &mut canvas.window(tile_area), active && idx == self.active_idx),
             }
         }
     }
@@ -139,8 +157,17 @@ impl Panels {
         }
     }
 
-    pub fn insert_column(&mut self, idx: usize, tile: Tile) {
-        self.columns.insert(idx, Column::single(tile));
+    pub fn from_tiles(tiles: Vec<Tile>, split: Split) -> Self {
+        Self {
+            active_idx: 0,
+            columns: match split {
+                Split::Vertical => tiles
+                    .into_iter()
+                    .map(Column::single)
+                    .collect(),
+                Split::Horizontal => vec![Column::many(tiles)],
+            },
+        }
     }
 
     fn column_area(&self, size: Extent2<usize>, idx: usize) -> Rect<usize, usize> {
@@ -186,6 +213,28 @@ impl Panels {
             true
         }
     }
+
+    // Make the nearest editor active, creating one if there are none
+    fn focus_editor(&mut self, ctx: &mut Context) {
+        if let Some(Tile::Editor(_)) = self.active_mut().and_then(|col| col.active_mut()) {
+            return;
+        }
+        let mut order = (0..self.columns.len()).collect::<Vec<_>>();
+        order.sort_by_key(|idx| (*idx as isize - self.active_idx as isize).abs());
+        match order
+            .into_iter()
+            .find_map(|idx| Some((idx, self.columns[idx].find_editor()?)))
+        {
+            Some((idx, tile_idx)) => {
+                self.active_idx = idx;
+                self.columns[idx].active_idx = tile_idx;
+            },
+            None => {
+                self.columns.insert(self.active_idx + 1, Column::single(Tile::Editor(Editor::recent(ctx))));
+                self.active_idx += 1;
+            },
+        }
+    }
 }
 
 impl Element for Panels {
@@ -209,6 +258,19 @@ impl Element for Panels {
                 self.columns.insert(self.active_idx + 1, Column::single(Tile::Terminal(Terminal::default())));
                 self.active_idx += 1;
             },
+            Event::OpenProjectSearch => {
+                self.columns.insert(self.active_idx + 1, Column::single(Tile::ProjectSearch(ProjectSearch::new(ctx))));
+                self.active_idx += 1;
+            },
+            Event::OpenProjectReplace => {
+                self.columns.insert(self.active_idx + 1, Column::single(Tile::ProjectSearch(ProjectSearch::replace(ctx))));
+                self.active_idx += 1;
+            },
+            // Results are opened in an editor, rather than replacing the tile they were chosen from
+            event @ Event::OpenFileAt(_, _) => {
+                self.focus_editor(ctx);
+                self.active_mut().map(|col| col.handle(ctx, event));
+            },
             Event::CloseEditor => { let _ = self.close_editor(); },
             event => { self.active_mut().map(|col| col.handle(ctx, event)); },
         }
@@ -0,0 +1,434 @@
+use std::path::{Path, PathBuf};
+use vek::*;
+use crate::{
+    Canvas,
+    Event,
+    Dir,
+    Color,
+    SearchOption,
+    buffer::{
+        search::Query,
+        highlight::{Highlights, Region},
+    },
+    state::{FileReplacements, Hunk},
+};
+use super::{
+    Context,
+    Element,
+    Prompt,
+    Severity,
+};
+
+// Where typing goes when replacing
+#[derive(Copy, Clone, PartialEq)]
+enum Focus {
+    Query,
+    Replacement,
+    Results,
+}
+
+// Something that can be selected in the list of results
+enum Entry {
+    // A match to jump to
+    Match(PathBuf, Vec2<usize>),
+    // A hunk of a proposed replacement, by file and hunk index
+    Hunk(usize, usize),
+}
+
+// A line of the results, belonging to an entry unless it is a heading
+struct Row {
+    entry: Option<usize>,
+    // Where the row begins within the highlighted text of all rows
+    offset: usize,
+    text: String,
+    color: Color,
+}
+
+// Rows are highlighted as if they were lines of a single text
+fn push_row(rows: &mut Vec<Row>, entry: Option<usize>, text: String, color: Color) {
+    let offset = next_offset(rows);
+    rows.push(Row { entry, offset, text, color });
+}
+
+fn next_offset(rows: &[Row]) -> usize {
+    rows.last().map_or(0, |row| row.offset + row.text.chars().count() + 1)
+}
+
+pub struct ProjectSearch {
+    root: PathBuf,
+    prompt: Prompt,
+    query: Query,
+    // Only present when replacing
+    replacement: Option<Prompt>,
+    focus: Focus,
+    // The query (and replacement) that the results are for
+    searched: Option<(Query, String)>,
+    invalid: bool,
+    entries: Vec<Entry>,
+    rows: Vec<Row>,
+    files: Vec<FileReplacements>,
+    file_count: usize,
+    highlights: Highlights,
+    selected: usize,
+    loc: usize,
+    page_height: usize,
+}
+
+impl ProjectSearch {
+    pub fn new(ctx: &mut Context) -> Self {
+        Self::with_replacement(ctx, None)
+    }
+
+    pub fn replace(ctx: &mut Context) -> Self {
+        Self::with_replacement(ctx, Some(Prompt::default()))
+    }
+
+    fn with_replacement(ctx: &mut Context, replacement: Option<Prompt>) -> Self {
+        let root = std::env::current_dir()
+            .and_then(|dir| dir.canonicalize())
+            .unwrap_or_default();
+
+        let query = ctx.last_search.clone().unwrap_or_default();
+        let mut prompt = Prompt::default();
+        prompt.append(&query.text);
+
+        let mut this = Self {
+            root,
+            prompt,
+            query,
+            replacement,
+            focus: Focus::Query,
+            searched: None,
+            invalid: false,
+            entries: Vec::new(),
+            rows: Vec::new(),
+            files: Vec::new(),
+            file_count: 0,
+            highlights: Highlights::default(),
+            selected: 0,
+            loc: 0,
+            page_height: 1,
+        };
+        if !this.query.text.is_empty() {
+            this.search(ctx);
+        }
+        this
+    }
+
+    fn replacement_text(&self) -> String {
+        self.replacement
+            .as_ref()
+            .map(|prompt| prompt.get_text())
+            .unwrap_or_default()
+    }
+
+    fn name_of(&self, path: &Path) -> String {
+        path
+            .strip_prefix(&self.root)
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    }
+
+    fn search(&mut self, ctx: &mut Context) {
+        self.query.text = self.prompt.get_text();
+        ctx.last_search = Some(self.query.clone());
+        let with = self.replacement_text();
+        self.searched = Some((self.query.clone(), with.clone()));
+
+        self.entries.clear();
+        self.rows.clear();
+        self.files.clear();
+        let mut regions = Vec::new();
+
+        if self.replacement.is_some() {
+            let files = ctx.state.preview_project_replace(&self.root, &self.query, &with);
+            self.invalid = files.is_err();
+            self.files = files.unwrap_or_default();
+            self.file_count = self.files.len();
+
+            for (file_idx, file) in self.files.iter().enumerate() {
+                let name = self.name_of(&file.path);
+                let offset = next_offset(&self.rows);
+                regions.push((offset..offset + name.chars().count(), Region::Path));
+                push_row(&mut self.rows, None, name, Color::Reset);
+
+                for (hunk_idx, hunk) in file.hunks.iter().enumerate() {
+                    let entry = Some(self.entries.len());
+                    self.entries.push(Entry::Hunk(file_idx, hunk_idx));
+                    for (i, line) in hunk.old.lines().enumerate() {
+                        let offset = next_offset(&self.rows);
+                        regions.push((offset..offset + 5, Region::Numeric));
+                        push_row(&mut self.rows, entry, format!("{:>5} - {}", hunk.line + i + 1, line), ctx.theme.invalid_color);
+                    }
+                    for line in hunk.new.lines() {
+                        push_row(&mut self.rows, entry, format!("      + {}", line), ctx.theme.create_color);
+                    }
+                }
+            }
+        } else {
+            let files = ctx.state.search_project(&self.root, &self.query);
+            self.invalid = files.is_err();
+            let files = files.unwrap_or_default();
+
+            for file in files.iter() {
+                let name = self.name_of(&file.path);
+                for line in file.lines.iter() {
+                    let offset = next_offset(&self.rows);
+                    let prefix = format!("{}:{}: ", name, line.line + 1);
+                    let name_len = name.chars().count();
+                    let prefix_len = prefix.chars().count();
+                    regions.push((offset..offset + name_len, Region::Path));
+                    regions.push((offset + name_len + 1..offset + prefix_len - 2, Region::Numeric));
+                    for range in line.ranges.iter() {
+                        regions.push((offset + prefix_len + range.start..offset + prefix_len + range.end, Region::Match));
+                    }
+
+                    let col = line.ranges.first().map_or(0, |r| r.start);
+                    let entry = Some(self.entries.len());
+                    self.entries.push(Entry::Match(file.path.clone(), Vec2::new(col, line.line)));
+                    push_row(&mut self.rows, entry, format!("{}{}", prefix, line.text), Color::Reset);
+                }
+            }
+            self.file_count = files.len();
+        }
+
+        self.highlights = Highlights::from_regions(regions);
+        self.selected = 0;
+        self.loc = 0;
+    }
+
+    fn select(&mut self, idx: isize) {
+        self.selected = idx.max(0).min(self.entries.len() as isize - 1).max(0) as usize;
+    }
+
+    // Open the selected result in an editor, with the cursor at the match
+    fn open_selected(&mut self, ctx: &mut Context) {
+        let target = match self.entries.get(self.selected) {
+            Some(Entry::Match(path, loc)) => Some((path.clone(), *loc)),
+            Some(Entry::Hunk(file, hunk)) => self.files
+                .get(*file)
+                .and_then(|f| Some((f.path.clone(), Vec2::new(0, f.hunks.get(*hunk)?.line)))),
+            None => None,
+        };
+        if let Some((path, loc)) = target {
+            ctx.secondary_events.push_back(Event::OpenFileAt(path, loc));
+        }
+    }
+
+    fn hunk_mut(&mut self, entry: usize) -> Option<&mut Hunk> {
+        match self.entries.get(entry)? {
+            Entry::Hunk(file, hunk) => self.files.get_mut(*file)?.hunks.get_mut(*hunk),
+            Entry::Match(_, _) => None,
+        }
+    }
+
+    fn toggle_all(&mut self) {
+        let accept = self.files
+            .iter()
+            .flat_map(|f| f.hunks.iter())
+            .any(|h| !h.accepted);
+        self.files
+            .iter_mut()
+            .flat_map(|f| f.hunks.iter_mut())
+            .for_each(|h| h.accepted = accept);
+    }
+
+    fn apply(&mut self, ctx: &mut Context) {
+        let (changed, errors) = ctx.state.apply_project_replace(&self.files);
+        ctx.messages.push(Severity::Info, format!("Replaced in {} files", changed));
+        for err in errors {
+            ctx.messages.error(format!("Failed to replace: {}", err));
+        }
+        // Show whatever is left
+        self.search(ctx);
+    }
+}
+
+impl Element for ProjectSearch {
+    type Response = Result<(), Event>;
+
+    fn handle(&mut self, ctx: &mut Context, event: Event) -> Self::Response {
+        let replacing = self.replacement.is_some();
+        match event {
+            Event::CursorMove(Dir::Up, _) => self.select(self.selected as isize - 1),
+            Event::CursorMove(Dir::Down, _) => self.select(self.selected as isize + 1),
+            Event::PageMove(Dir::Up, _) => self.select(self.selected as isize - self.page_height as isize),
+            Event::PageMove(Dir::Down, _) => self.select(self.selected as isize + self.page_height as isize),
+            Event::Insert('\t') if replacing => self.focus = match self.focus {
+                Focus::Query => Focus::Replacement,
+                Focus::Replacement => Focus::Results,
+                Focus::Results => Focus::Query,
+            },
+            Event::Insert('\t') => self.select(self.selected as isize + 1),
+            Event::Insert('\n') => {
+                self.query.text = self.prompt.get_text();
+                let current = (self.query.clone(), self.replacement_text());
+                // The first press runs the search, later presses open the selected result
+                if self.searched.as_ref() != Some(&current) {
+                    self.search(ctx);
+                    if replacing {
+                        self.focus = Focus::Results;
+                    }
+                } else {
+                    self.open_selected(ctx);
+                }
+            },
+            Event::Insert(' ') if self.focus == Focus::Results => {
+                if let Some(hunk) = self.hunk_mut(self.selected) {
+                    hunk.accepted ^= true;
+                }
+            },
+            Event::Insert('a') if self.focus == Focus::Results => self.toggle_all(),
+            Event::ReplaceAll if replacing => self.apply(ctx),
+            Event::ToggleSearchOption(option) => match option {
+                SearchOption::CaseSensitive => self.query.case_sensitive ^= true,
+                SearchOption::WholeWord => self.query.whole_word ^= true,
+                SearchOption::Regex => self.query.regex ^= true,
+                SearchOption::InSelection => {},
+            },
+            event => match (self.focus, &mut self.replacement) {
+                (Focus::Replacement, Some(replacement)) => replacement.handle(ctx, event)?,
+                (Focus::Results, _) => return Err(event),
+                _ => self.prompt.handle(ctx, event)?,
+            },
+        }
+        Ok(())
+    }
+
+    fn update(&mut self, ctx: &mut Context, canvas: &mut impl Canvas, active: bool) {
+        let header_height = if self.replacement.is_some() { 3 } else { 2 };
+        self.page_height = canvas.size().h.saturating_sub(header_height + 2).max(1);
+
+        // Keep the rows of the selected entry in view
+        let selected_rows = self.rows
+            .iter()
+            .enumerate()
+            .filter(|(_, row)| row.entry == Some(self.selected))
+            .map(|(i, _)| i);
+        if let (Some(first), Some(last)) = (selected_rows.clone().min(), selected_rows.max()) {
+            self.loc = self.loc
+                .max((last + 1).saturating_sub(self.page_height))
+                .min(first);
+        }
+
+        self.prompt.set_fg_color(if self.invalid {
+            ctx.theme.invalid_color
+        } else {
+            Color::Rgb(Rgb::broadcast(255))
+        });
+        self.prompt.update(ctx, canvas, active && self.focus == Focus::Query);
+        if let Some(replacement) = &mut self.replacement {
+            replacement.update(ctx, canvas, active && self.focus == Focus::Replacement);
+        }
+    }
+
+    fn render(&self, ctx: &mut Context, canvas: &mut impl Canvas, active: bool) {
+        let sz = canvas.size();
+
+        // Frame
+        canvas
+            .with_fg(Color::Rgb(if active {
+                Rgb::broadcast(255)
+            } else {
+                Rgb::broadcast(100)
+            }))
+            .frame();
+
+        // Title
+        let title = if self.replacement.is_some() {
+            format!("[Project Replace: {} changes in {} files]", self.entries.len(), self.file_count)
+        } else {
+            format!("[Project Search: {} lines in {} files]", self.entries.len(), self.file_count)
+        };
+        canvas.write_str(Vec2::new((sz.w.saturating_sub(title.len())) / 2, 0), &title);
+
+        let mut canvas = canvas.window(Rect::new(1, 1, sz.w.saturating_sub(2), sz.h.saturating_sub(2)));
+        let sz = canvas.size();
+        canvas.rectangle(Vec2::zero(), sz, ' ');
+
+        // Modes
+        let modes = [
+            ("Aa", self.query.case_sensitive),
+            ("\\b", self.query.whole_word),
+            (".*", self.query.regex),
+        ];
+        let modes_w = modes.len() * 3;
+        for (i, (name, enabled)) in modes.iter().enumerate() {
+            canvas
+                .with_fg(if *enabled { Color::Rgb(Rgb::broadcast(255)) } else { ctx.theme.subtle_color })
+                .with_bg(if *enabled { ctx.theme.selection_color } else { Color::Reset })
+                .write_str(Vec2::new(sz.w.saturating_sub(modes_w) + i * 3, 0), name);
+        }
+
+        self.prompt.render(ctx, &mut canvas.window(Rect::new(
+            1,
+            0,
+            sz.w.saturating_sub(modes_w + 2),
+            1,
+        )), active && self.focus == Focus::Query);
+
+        let mut header_height = 1;
+        if let Some(replacement) = &self.replacement {
+            canvas
+                .with_fg(ctx.theme.subtle_color)
+                .write_str(Vec2::new(1, 1), "=>");
+            replacement.render(ctx, &mut canvas.window(Rect::new(
+                4,
+                1,
+                sz.w.saturating_sub(5),
+                1,
+            )), active && self.focus == Focus::Replacement);
+            header_height += 1;
+        }
+
+        canvas
+            .with_fg(ctx.theme.subtle_color)
+            .rectangle(Vec2::new(0, header_height), Extent2::new(sz.w, 1), '-');
+        header_height += 1;
+
+        // Results
+        let mut prev_entry = None;
+        for (y, row) in self.rows
+            .iter()
+            .skip(self.loc)
+            .take(sz.h.saturating_sub(header_height))
+            .enumerate()
+        {
+            let y = header_height + y;
+            let selected = row.entry == Some(self.selected) && row.entry.is_some();
+
+            // Hunks can be accepted or rejected individually
+            let indent = match row.entry.map(|entry| &self.entries[entry]) {
+                Some(Entry::Hunk(file, hunk)) => {
+                    if prev_entry != row.entry {
+                        let accepted = self.files[*file].hunks[*hunk].accepted;
+                        canvas
+                            .with_fg(if accepted { ctx.theme.create_color } else { ctx.theme.subtle_color })
+                            .write_str(Vec2::new(0, y), if accepted { "[x]" } else { "[ ]" });
+                    }
+                    4
+                },
+                _ => 0,
+            };
+            prev_entry = row.entry;
+
+            for (x, c) in row.text.chars().enumerate().take(sz.w.saturating_sub(indent)) {
+                let region = self.highlights.get_at(row.offset + x);
+                canvas
+                    .with_fg(match region {
+                        Region::Normal => row.color,
+                        region => ctx.theme.get_highlight_color(region),
+                    })
+                    .with_bg(if selected {
+                        ctx.theme.selection_color
+                    } else if let Region::Match = region {
+                        ctx.theme.search_color
+                    } else {
+                        Color::Reset
+                    })
+                    .write_char(Vec2::new(indent + x, y), if c == '\t' { ' ' } else { c });
+            }
+        }
+    }
+}
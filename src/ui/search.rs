@@ -0,0 +1,306 @@
+use std::ops::Range;
+use vek::*;
+use crate::{
+    Canvas,
+    Event,
+    Dir,
+    BufferHandle,
+    Color,
+    Cursor,
+    SearchOption,
+    buffer::search::Query,
+};
+use super::{
+    Context,
+    Element,
+    Prompt,
+    Severity,
+};
+
+pub struct Search {
+    prompt: Prompt,
+    buffer: BufferHandle,
+    prev_cursor: Cursor,
+    query: Query,
+    matches: Vec<Range<usize>>,
+    current: Option<usize>,
+    invalid: bool,
+    // Only present when replacing
+    replacement: Option<Prompt>,
+    editing_replacement: bool,
+    // The selection when the search began, which matches may be restricted to
+    scope: Option<Range<usize>>,
+    in_selection: bool,
+}
+
+impl Search {
+    pub fn new(ctx: &mut Context, buffer: BufferHandle) -> Self {
+        let prev_cursor = *ctx.state
+            .get_buffer_mut(&buffer)
+            .unwrap()
+            .cursor();
+
+        // Start from the previous search, so that it can easily be repeated
+        let query = ctx.last_search.clone().unwrap_or_default();
+        let mut prompt = Prompt::default();
+        prompt.append(&query.text);
+
+        let scope = if prev_cursor.is_reaching() {
+            Some(prev_cursor.base.min(prev_cursor.pos)..prev_cursor.base.max(prev_cursor.pos))
+        } else {
+            None
+        };
+
+        let mut this = Self {
+            prompt,
+            buffer,
+            prev_cursor,
+            query,
+            matches: Vec::new(),
+            current: None,
+            invalid: false,
+            replacement: None,
+            editing_replacement: false,
+            scope,
+            in_selection: false,
+        };
+        this.refresh(ctx);
+        this
+    }
+
+    pub fn replace(ctx: &mut Context, buffer: BufferHandle) -> Self {
+        Self {
+            replacement: Some(Prompt::default()),
+            ..Self::new(ctx, buffer)
+        }
+    }
+
+    pub fn cancel(self, ctx: &mut Context) {
+        if let Some(mut buf) = ctx.state.get_buffer_mut(&self.buffer) {
+            *buf.cursor_mut() = self.prev_cursor;
+        }
+    }
+
+    // Search again, moving to the first match after where the search began
+    fn refresh(&mut self, ctx: &mut Context) {
+        self.query.text = self.prompt.get_text();
+        ctx.last_search = Some(self.query.clone());
+
+        let buf = match ctx.state.get_buffer_mut(&self.buffer) {
+            Some(buf) => buf,
+            None => return,
+        };
+        match self.query.find_all(buf.content()) {
+            Ok(matches) => {
+                self.matches = match (&self.scope, self.in_selection) {
+                    (Some(scope), true) => matches
+                        .into_iter()
+                        .filter(|m| m.start >= scope.start && m.end <= scope.end)
+                        .collect(),
+                    _ => matches,
+                };
+                self.invalid = false;
+            },
+            Err(_) => {
+                self.matches.clear();
+                self.invalid = true;
+            },
+        }
+
+        let from = self.prev_cursor.base.min(self.prev_cursor.pos);
+        let first = self.matches
+            .iter()
+            .position(|m| m.start >= from)
+            .or(if self.matches.is_empty() { None } else { Some(0) });
+        self.select(ctx, first);
+    }
+
+    fn select(&mut self, ctx: &mut Context, idx: Option<usize>) {
+        self.current = idx;
+        if let Some(mut buf) = ctx.state.get_buffer_mut(&self.buffer) {
+            *buf.cursor_mut() = match idx.and_then(|idx| self.matches.get(idx)) {
+                Some(m) => Cursor { base: m.start, pos: m.end, preferred_x: None },
+                None => self.prev_cursor,
+            };
+        }
+    }
+
+    fn step(&mut self, ctx: &mut Context, forward: bool) {
+        let len = self.matches.len();
+        if len > 0 {
+            let idx = match self.current {
+                Some(idx) if forward => (idx + 1) % len,
+                Some(idx) => (idx + len - 1) % len,
+                None => 0,
+            };
+            self.select(ctx, Some(idx));
+        }
+    }
+
+    fn replacement_text(&self) -> String {
+        self.replacement
+            .as_ref()
+            .map(|prompt| prompt.get_text())
+            .unwrap_or_default()
+    }
+
+    // Replace the current match and move on to the next one
+    fn replace_current(&mut self, ctx: &mut Context) {
+        let current = match self.current.and_then(|idx| self.matches.get(idx)) {
+            Some(m) => m.clone(),
+            None => return,
+        };
+        let with = self.replacement_text();
+        let mut buf = match ctx.state.get_buffer_mut(&self.buffer) {
+            Some(buf) => buf,
+            None => return,
+        };
+        let replacement = match self.query.replacements(buf.content(), current.clone(), &with) {
+            Ok(replacements) => match replacements.into_iter().find(|(r, _)| *r == current) {
+                Some(replacement) => replacement,
+                None => return,
+            },
+            Err(_) => return,
+        };
+        buf.replace_ranges(std::slice::from_ref(&replacement));
+
+        let len = replacement.1.chars().count();
+        if let Some(scope) = &mut self.scope {
+            scope.end = scope.end - current.len() + len;
+        }
+        // Continue searching from after the replacement
+        self.prev_cursor = Cursor { base: current.start + len, pos: current.start + len, preferred_x: None };
+        self.refresh(ctx);
+    }
+
+    fn replace_all(&mut self, ctx: &mut Context) {
+        let with = self.replacement_text();
+        let mut buf = match ctx.state.get_buffer_mut(&self.buffer) {
+            Some(buf) => buf,
+            None => return,
+        };
+        let within = match (&self.scope, self.in_selection) {
+            (Some(scope), true) => scope.clone(),
+            _ => 0..buf.len(),
+        };
+        match self.query.replacements(buf.content(), within, &with) {
+            Ok(replacements) => {
+                // The edits shift the original cursor to where it should end up
+                *buf.cursor_mut() = self.prev_cursor;
+                buf.replace_ranges(&replacements);
+                ctx.messages.push(Severity::Info, format!("Replaced {} matches", replacements.len()));
+                ctx.secondary_events.push_back(Event::CloseMenu);
+            },
+            Err(err) => ctx.messages.error(format!("Invalid search: {}", err)),
+        }
+    }
+}
+
+impl Element for Search {
+    type Response = Result<(), Event>;
+
+    fn handle(&mut self, ctx: &mut Context, event: Event) -> Self::Response {
+        match event {
+            Event::CursorMove(Dir::Up, _) => self.step(ctx, false),
+            Event::Insert('\t') if self.replacement.is_some() => self.editing_replacement ^= true,
+            Event::CursorMove(Dir::Down, _) | Event::Insert('\t') => self.step(ctx, true),
+            Event::Insert('\n') if self.replacement.is_some() => self.replace_current(ctx),
+            Event::Insert('\n') => ctx.secondary_events.push_back(Event::CloseMenu),
+            Event::ReplaceAll if self.replacement.is_some() => self.replace_all(ctx),
+            Event::ToggleSearchOption(option) => {
+                match option {
+                    SearchOption::CaseSensitive => self.query.case_sensitive ^= true,
+                    SearchOption::WholeWord => self.query.whole_word ^= true,
+                    SearchOption::Regex => self.query.regex ^= true,
+                    SearchOption::InSelection => self.in_selection ^= self.scope.is_some(),
+                }
+                self.refresh(ctx);
+            },
+            event if self.editing_replacement => {
+                if let Some(replacement) = &mut self.replacement {
+                    replacement.handle(ctx, event)?;
+                }
+            },
+            event => {
+                let old_text = self.prompt.get_text();
+                self.prompt.handle(ctx, event)?;
+                if self.prompt.get_text() != old_text {
+                    self.refresh(ctx);
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn update(&mut self, ctx: &mut Context, canvas: &mut impl Canvas, active: bool) {
+        self.prompt.set_fg_color(if self.invalid || (self.matches.is_empty() && !self.query.text.is_empty()) {
+            ctx.theme.invalid_color
+        } else {
+            Color::Rgb(Rgb::new(255, 255, 255))
+        });
+
+        ctx.search_matches = Some((self.buffer.buffer_id, self.matches.clone()));
+
+        self.prompt.update(ctx, canvas, active && !self.editing_replacement);
+        if let Some(replacement) = &mut self.replacement {
+            replacement.update(ctx, canvas, active && self.editing_replacement);
+        }
+    }
+
+    fn render(&self, ctx: &mut Context, canvas: &mut impl Canvas, active: bool) {
+        let height = if self.replacement.is_some() { 4 } else { 3 };
+        let sz = canvas.size();
+        let mut canvas = canvas.window(Rect::new(
+            sz.w / 4,
+            sz.h.saturating_sub(height + 1),
+            sz.w.saturating_sub(sz.w / 2),
+            height,
+        ));
+
+        // Frame
+        let sz = canvas.size();
+        canvas.rectangle(Vec2::zero(), sz, ' ');
+        canvas.frame();
+
+        let name = if self.replacement.is_some() { "Replace" } else { "Search" };
+        let title = match self.current {
+            Some(idx) => format!("[{} {}/{}]", name, idx + 1, self.matches.len()),
+            None => format!("[{} 0/{}]", name, self.matches.len()),
+        };
+        canvas.write_str(Vec2::new((sz.w.saturating_sub(title.len())) / 2, 0), &title);
+
+        // Modes
+        let modes = [
+            ("Aa", self.query.case_sensitive),
+            ("\\b", self.query.whole_word),
+            (".*", self.query.regex),
+            ("[]", self.in_selection),
+        ];
+        let modes_w = modes.len() * 3;
+        for (i, (name, enabled)) in modes.iter().enumerate() {
+            canvas
+                .with_fg(if *enabled { Color::Rgb(Rgb::new(255, 255, 255)) } else { ctx.theme.subtle_color })
+                .with_bg(if *enabled { ctx.theme.selection_color } else { Color::Reset })
+                .write_str(Vec2::new(sz.w.saturating_sub(modes_w + 1) + i * 3, 1), name);
+        }
+
+        self.prompt.render(ctx, &mut canvas.window(Rect::new(
+            2,
+            1,
+            sz.w.saturating_sub(modes_w + 4),
+            1,
+        )), active && !self.editing_replacement);
+
+        if let Some(replacement) = &self.replacement {
+            canvas
+                .with_fg(ctx.theme.subtle_color)
+                .write_str(Vec2::new(2, 2), "=>");
+            replacement.render(ctx, &mut canvas.window(Rect::new(
+                5,
+                2,
+                sz.w.saturating_sub(7),
+                1,
+            )), active && self.editing_replacement);
+        }
+    }
+}
@@ -14,6 +14,9 @@ pub struct Theme {
     pub scrollbar_color: Color,
     pub scrollpad_color: Color,
     pub selection_color: Color,
+    pub cursor_color: Color,
+    pub search_color: Color,
+    pub bracket_color: Color,
     pub create_color: Color,
     pub invalid_color: Color,
     pub subtle_color: Color,
@@ -40,6 +43,7 @@ impl Theme {
             Region::Error => Color::Rgb(Rgb::new(225, 0, 0)),
             Region::Warning => Color::Rgb(Rgb::new(255, 180, 50)),
             Region::Info => Color::Rgb(Rgb::new(0, 200, 75)),
+            Region::Match => Color::Rgb(Rgb::broadcast(255)),
             _ => Color::Reset,
         }
     }
@@ -55,6 +59,9 @@ impl Default for Theme {
             scrollbar_color: Color::Rgb(Rgb::gray(70)),
             scrollpad_color: Color::Rgb(Rgb::gray(175)),
             selection_color: Color::Rgb(Rgb::new(0, 100, 80)),
+            cursor_color: Color::Rgb(Rgb::gray(180)),
+            search_color: Color::Rgb(Rgb::new(90, 70, 0)),
+            bracket_color: Color::Rgb(Rgb::new(60, 60, 130)),
             create_color: Color::Green,
             invalid_color: Color::Red,
             subtle_color: Color::Rgb(Rgb::gray(150)),
@@ -0,0 +1,174 @@
+use std::time::{Instant, Duration};
+use vek::*;
+use crate::{
+    Canvas,
+    Event,
+    Dir,
+    BufferHandle,
+    Color,
+    buffer::history::NodeId,
+};
+use super::{
+    Context,
+    Element,
+    Prompt,
+};
+
+pub struct UndoTree {
+    prompt: Prompt,
+    buffer: BufferHandle,
+    prev_node: NodeId,
+    nodes: Vec<(NodeId, usize)>,
+    selected: usize,
+}
+
+impl UndoTree {
+    pub fn new(ctx: &mut Context, buffer: BufferHandle) -> Self {
+        let history = ctx.state
+            .get_buffer(&buffer)
+            .unwrap()
+            .history();
+        let nodes = history.walk();
+        let prev_node = history.current();
+        Self {
+            prompt: Prompt::default(),
+            selected: nodes.iter().position(|(id, _)| *id == prev_node).unwrap_or(0),
+            buffer,
+            prev_node,
+            nodes,
+        }
+    }
+
+    pub fn cancel(self, ctx: &mut Context) {
+        if let Some(mut buf) = ctx.state.get_buffer_mut(&self.buffer) {
+            buf.undo_to(self.prev_node);
+        }
+    }
+
+    fn select(&mut self, ctx: &mut Context, selected: usize) {
+        self.selected = selected;
+        if let (Some(mut buf), Some((id, _))) = (ctx.state.get_buffer_mut(&self.buffer), self.nodes.get(selected)) {
+            buf.undo_to(*id);
+        }
+    }
+}
+
+impl Element for UndoTree {
+    type Response = Result<(), Event>;
+
+    fn handle(&mut self, ctx: &mut Context, event: Event) -> Self::Response {
+        let len = self.nodes.len().max(1);
+        match event {
+            Event::CursorMove(Dir::Up, _) => self.select(ctx, (self.selected + len - 1) % len),
+            Event::CursorMove(Dir::Down, _) => self.select(ctx, (self.selected + 1) % len),
+            Event::Insert('\n') => ctx.secondary_events.push_back(Event::CloseMenu),
+            event => {
+                self.prompt.handle(ctx, event)?;
+
+                // Jump to the buffer as it was the given number of minutes ago
+                if let Ok(mins) = self.prompt.get_text().trim().parse::<u64>() {
+                    let time = Instant::now()
+                        .checked_sub(Duration::from_secs(mins * 60));
+                    let node = match (time, ctx.state.get_buffer(&self.buffer)) {
+                        (Some(time), Some(buf)) => buf.history().at_time(time),
+                        (None, Some(buf)) => buf.history().root(),
+                        _ => return Ok(()),
+                    };
+                    if let Some(idx) = self.nodes.iter().position(|(id, _)| *id == node) {
+                        self.select(ctx, idx);
+                    }
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn update(&mut self, ctx: &mut Context, canvas: &mut impl Canvas, active: bool) {
+        let valid = self.prompt.get_text().is_empty() || self.prompt.get_text().trim().parse::<u64>().is_ok();
+        self.prompt.set_fg_color(if valid {
+            Color::Rgb(Rgb::new(255, 255, 255))
+        } else {
+            ctx.theme.invalid_color
+        });
+
+        self.prompt.update(ctx, canvas, active);
+    }
+
+    fn render(&self, ctx: &mut Context, canvas: &mut impl Canvas, active: bool) {
+        let sz = canvas.size();
+        let mut canvas = canvas.window(Rect::new(
+            sz.w / 4,
+            sz.h / 4,
+            sz.w.saturating_sub(sz.w / 2),
+            sz.h.saturating_sub(sz.h / 2),
+        ));
+
+        // Frame
+        let sz = canvas.size();
+        canvas.rectangle(Vec2::zero(), sz, ' ');
+        canvas.frame();
+
+        let title = "[Undo Tree (minutes ago)]";
+        canvas.write_str(Vec2::new((sz.w.saturating_sub(title.len())) / 2, 0), title);
+
+        self.prompt.render(ctx, &mut canvas.window(Rect::new(
+            2,
+            1,
+            canvas.size().w.saturating_sub(3),
+            canvas.size().h,
+        )), active);
+
+        // Entries
+        let mut canvas = canvas.window(Rect::new(
+            1,
+            2,
+            canvas.size().w.saturating_sub(2),
+            canvas.size().h.saturating_sub(3),
+        ));
+
+        let history = match ctx.state.get_buffer(&self.buffer) {
+            Some(buf) => buf.history(),
+            None => return,
+        };
+
+        let now = Instant::now();
+        for (y, (idx, (id, depth))) in self.nodes
+            .iter()
+            .enumerate()
+            .skip(self.selected.saturating_sub(canvas.size().h.saturating_sub(1)))
+            .take(canvas.size().h)
+            .enumerate()
+        {
+            let node = match history.node(*id) {
+                Some(node) => node,
+                None => continue,
+            };
+
+            let bg_color = if idx == self.selected {
+                ctx.theme.selection_color
+            } else {
+                Color::Reset
+            };
+
+            let marker = if *id == history.current() { '●' } else { '○' };
+            let desc = if *id == history.root() {
+                "original".to_string()
+            } else {
+                format!("{} edits", node.change.edits.len())
+            };
+            let age = node.time.map_or(String::new(), |t| {
+                let secs = now.saturating_duration_since(t).as_secs();
+                if secs < 60 {
+                    format!("{}s ago", secs)
+                } else {
+                    format!("{}m ago", secs / 60)
+                }
+            });
+
+            canvas
+                .with_fg(Color::Rgb(Rgb::new(255, 255, 255)))
+                .with_bg(bg_color)
+                .write_str(Vec2::new(1, y), &format!("{:<40}{:>8}", format!("{}{} {}", "  ".repeat(*depth), marker, desc), age));
+        }
+    }
+}